@@ -14,7 +14,7 @@ use ipfs_webdav::api::BaseApi;
 async fn main() {
     env_logger::init();
 
-    let api = BaseApi::new();
+    let api = BaseApi::new().with_time_store("ipfs-webdav-times.json");
     let dav_server = ipfs_webdav::make_server(api);
 
     let make_service = hyper::service::make_service_fn(move |_| {
@@ -7,42 +7,70 @@
 //
 
 use std::fmt::{Debug, Formatter};
+use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
-use bytes::{Buf, Bytes};
+use bytes::{Buf, Bytes, BytesMut};
+use futures::stream::{self, BoxStream, StreamExt};
 use futures::TryStreamExt;
-use ipfs_api_backend_hyper::request::{FilesLs, FilesRead, FilesWrite};
-use ipfs_api_backend_hyper::response::{FilesEntry, FilesStatResponse};
+use ipfs_api_backend_hyper::request::{
+    Add, FilesChcid, FilesCp, FilesMkdir, FilesMv, FilesRead, FilesRm, FilesWrite,
+};
 use ipfs_api_backend_hyper::{Error, IpfsApi, IpfsClient, TryFromUri};
+use ipfs_api_prelude::Backend;
+
+pub use crate::error::PeerError;
+#[cfg(feature = "memory")]
+pub use crate::memory::InMemoryApi;
+use crate::rpc::{self, FilesEntry, FilesStatResponse};
+use crate::times::{TimeStore, Times};
+
+/// Size of the chunks the default `PeerApi::write_from` passes to `PeerApi::write`.
+const WRITE_CHUNK: usize = 1024 * 1024;
+
+/// Contents of a buffered upload, held in memory or in a spool file.
+pub type PeerBody = Box<dyn Read + Send + Sync>;
+
+/// Contents of a file as it is read from the IPFS node, chunk by chunk.
+pub type PeerStream = BoxStream<'static, Result<Bytes, PeerError>>;
 
 /// Trait that defines the interface for interaction with IPFS RPC API.
 #[async_trait]
 pub trait PeerApi: Send + Sync + Debug {
     /// Add references to IPFS files and directories in MFS (or copy within MFS).
-    async fn cp(&self, path: &str, dest: &str) -> Result<(), Error>;
+    async fn cp(&self, path: &str, dest: &str) -> Result<(), PeerError>;
 
     /// Flush a given path's data to disk.
-    async fn flush(&self, path: &str) -> Result<(), Error>;
+    async fn flush(&self, path: &str) -> Result<(), PeerError>;
 
     /// List directories in the local mutable namespace.
-    async fn ls(&self, path: &str) -> Result<Vec<PeerEntry>, Error>;
+    async fn ls(&self, path: &str) -> Result<Vec<PeerEntry>, PeerError>;
 
     /// Make directories.
-    async fn mkdir(&self, path: &str) -> Result<PeerEntry, Error>;
+    async fn mkdir(&self, path: &str) -> Result<PeerEntry, PeerError>;
 
     /// Move files.
-    async fn mv(&self, path: &str, dest: &str) -> Result<(), Error>;
+    async fn mv(&self, path: &str, dest: &str) -> Result<(), PeerError>;
 
     /// Read a file in a given MFS.
-    async fn read(&self, path: &str, offset: usize, count: usize) -> Result<Bytes, Error>;
+    async fn read(&self, path: &str, offset: usize, count: usize) -> Result<Bytes, PeerError>;
+
+    /// Stream a file in a given MFS from an offset to its end.
+    ///
+    /// The default implementation reads the whole remainder with a single `read`.
+    async fn read_stream(&self, path: &str, offset: usize) -> Result<PeerStream, PeerError> {
+        let data = self.read(path, offset, i64::MAX as usize).await?;
+        Ok(stream::once(async { Ok(data) }).boxed())
+    }
 
     /// Remove a file.
-    async fn rm(&self, path: &str) -> Result<(), Error>;
+    async fn rm(&self, path: &str) -> Result<(), PeerError>;
 
     /// Display file status.
-    async fn stat(&self, path: &str) -> Result<PeerEntry, Error>;
+    async fn stat(&self, path: &str) -> Result<PeerEntry, PeerError>;
 
     /// Write to a mutable file in a given filesystem.
     async fn write(
@@ -51,7 +79,204 @@ pub trait PeerApi: Send + Sync + Debug {
         offset: usize,
         truncate: bool,
         data: Bytes,
-    ) -> Result<(), Error>;
+    ) -> Result<(), PeerError>;
+
+    /// Write a whole upload to a mutable file, reading it from the given body.
+    ///
+    /// The default implementation passes the body to `write` in chunks and ignores the
+    /// DAG options, backends that can stream it in a single request should override it.
+    async fn write_from(
+        &self,
+        path: &str,
+        mut offset: usize,
+        mut truncate: bool,
+        mut data: PeerBody,
+        _options: &DagOptions,
+    ) -> Result<(), PeerError> {
+        loop {
+            let mut chunk = Vec::new();
+            (&mut data)
+                .take(WRITE_CHUNK as u64)
+                .read_to_end(&mut chunk)
+                .map_err(PeerError::other)?;
+            let len = chunk.len();
+            if len > 0 || truncate {
+                self.write(path, offset, truncate, chunk.into()).await?;
+            }
+            if len < WRITE_CHUNK {
+                return Ok(());
+            }
+            offset += len;
+            truncate = false;
+        }
+    }
+
+    /// Import a whole file with `ipfs add` and link it into MFS, replacing what is there.
+    ///
+    /// The default implementation writes the body with `write_from` instead.
+    async fn add(&self, path: &str, data: PeerBody, options: &DagOptions) -> Result<(), PeerError> {
+        self.write_from(path, 0, true, data, options).await
+    }
+
+    /// Make a directory whose CID follows the given DAG options.
+    ///
+    /// The default implementation ignores the options.
+    async fn mkdir_with(&self, path: &str, _options: &DagOptions) -> Result<PeerEntry, PeerError> {
+        self.mkdir(path).await
+    }
+
+    /// Change the CID version or hash function of an existing directory.
+    ///
+    /// Backends without real CIDs can keep the default no-op.
+    async fn chcid(&self, _path: &str, _options: &DagOptions) -> Result<(), PeerError> {
+        Ok(())
+    }
+
+    /// Change the modification time of a file or directory.
+    ///
+    /// Backends that don't track modification times can keep the default no-op.
+    async fn touch(&self, _path: &str, _mtime: SystemTime) -> Result<(), PeerError> {
+        Ok(())
+    }
+
+    /// List a directory outside MFS, given as an `/ipfs/<cid>/...` path.
+    ///
+    /// The default implementation finds nothing, backends that can reach content
+    /// by CID should override it along with `ipfs_stat` and `cat`.
+    async fn ipfs_ls(&self, _path: &str) -> Result<Vec<PeerEntry>, PeerError> {
+        Err(PeerError::NotFound)
+    }
+
+    /// Display the status of a file or directory outside MFS, given as an `/ipfs/` path.
+    async fn ipfs_stat(&self, _path: &str) -> Result<PeerEntry, PeerError> {
+        Err(PeerError::NotFound)
+    }
+
+    /// Stream a file outside MFS, given as an `/ipfs/` path, from an offset to its end.
+    async fn cat(&self, _path: &str, _offset: usize) -> Result<PeerStream, PeerError> {
+        Err(PeerError::NotFound)
+    }
+
+    /// Resolve an IPNS name to the `/ipfs/<cid>` path it points to.
+    async fn resolve_name(&self, _name: &str) -> Result<String, PeerError> {
+        Err(PeerError::NotFound)
+    }
+
+    /// List the keys the node can publish IPNS records with.
+    ///
+    /// The default implementation has none, which makes publishing fail.
+    async fn key_list(&self) -> Result<Vec<PeerKey>, PeerError> {
+        Ok(Vec::new())
+    }
+
+    /// Publish a path, usually `/ipfs/<cid>`, under the IPNS name of a key and
+    /// return that name.
+    async fn name_publish(&self, _path: &str, _key: &str) -> Result<String, PeerError> {
+        Err(PeerError::NotFound)
+    }
+}
+
+/// Lets several handlers, or a handler and its embedder, share one API instance.
+#[async_trait]
+impl<T: PeerApi + ?Sized> PeerApi for Arc<T> {
+    async fn cp(&self, path: &str, dest: &str) -> Result<(), PeerError> {
+        (**self).cp(path, dest).await
+    }
+
+    async fn flush(&self, path: &str) -> Result<(), PeerError> {
+        (**self).flush(path).await
+    }
+
+    async fn ls(&self, path: &str) -> Result<Vec<PeerEntry>, PeerError> {
+        (**self).ls(path).await
+    }
+
+    async fn mkdir(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        (**self).mkdir(path).await
+    }
+
+    async fn mv(&self, path: &str, dest: &str) -> Result<(), PeerError> {
+        (**self).mv(path, dest).await
+    }
+
+    async fn read(&self, path: &str, offset: usize, count: usize) -> Result<Bytes, PeerError> {
+        (**self).read(path, offset, count).await
+    }
+
+    async fn read_stream(&self, path: &str, offset: usize) -> Result<PeerStream, PeerError> {
+        (**self).read_stream(path, offset).await
+    }
+
+    async fn rm(&self, path: &str) -> Result<(), PeerError> {
+        (**self).rm(path).await
+    }
+
+    async fn stat(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        (**self).stat(path).await
+    }
+
+    async fn write(
+        &self,
+        path: &str,
+        offset: usize,
+        truncate: bool,
+        data: Bytes,
+    ) -> Result<(), PeerError> {
+        (**self).write(path, offset, truncate, data).await
+    }
+
+    async fn write_from(
+        &self,
+        path: &str,
+        offset: usize,
+        truncate: bool,
+        data: PeerBody,
+        options: &DagOptions,
+    ) -> Result<(), PeerError> {
+        (**self)
+            .write_from(path, offset, truncate, data, options)
+            .await
+    }
+
+    async fn add(&self, path: &str, data: PeerBody, options: &DagOptions) -> Result<(), PeerError> {
+        (**self).add(path, data, options).await
+    }
+
+    async fn mkdir_with(&self, path: &str, options: &DagOptions) -> Result<PeerEntry, PeerError> {
+        (**self).mkdir_with(path, options).await
+    }
+
+    async fn chcid(&self, path: &str, options: &DagOptions) -> Result<(), PeerError> {
+        (**self).chcid(path, options).await
+    }
+
+    async fn touch(&self, path: &str, mtime: SystemTime) -> Result<(), PeerError> {
+        (**self).touch(path, mtime).await
+    }
+
+    async fn ipfs_ls(&self, path: &str) -> Result<Vec<PeerEntry>, PeerError> {
+        (**self).ipfs_ls(path).await
+    }
+
+    async fn ipfs_stat(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        (**self).ipfs_stat(path).await
+    }
+
+    async fn cat(&self, path: &str, offset: usize) -> Result<PeerStream, PeerError> {
+        (**self).cat(path, offset).await
+    }
+
+    async fn resolve_name(&self, name: &str) -> Result<String, PeerError> {
+        (**self).resolve_name(name).await
+    }
+
+    async fn key_list(&self) -> Result<Vec<PeerKey>, PeerError> {
+        (**self).key_list().await
+    }
+
+    async fn name_publish(&self, path: &str, key: &str) -> Result<String, PeerError> {
+        (**self).name_publish(path, key).await
+    }
 }
 
 /// IPFS node MFS (mutable file system) entity representation.
@@ -71,38 +296,78 @@ pub struct PeerEntry {
 
     /// Size of MFS entity.
     pub size: usize,
+
+    /// Content identifier (CID) of MFS entity, if the backend knows it.
+    pub cid: Option<String>,
 }
 
 impl PeerEntry {
-    fn new_dir(path: &str) -> Self {
+    fn from_stat(path: &str, stat: &FilesStatResponse, times: Times) -> Self {
         Self {
             path: path.to_string(),
-            crtime: SystemTime::now(),
-            mtime: SystemTime::now(),
-            is_dir: true,
-            size: 0,
-        }
-    }
-
-    fn from_stat(path: &str, stat: &FilesStatResponse) -> Self {
-        Self {
-            path: path.to_string(),
-            crtime: SystemTime::now(),
-            mtime: SystemTime::now(),
+            crtime: times.crtime,
+            mtime: times.mtime,
             is_dir: stat.typ == "directory",
             size: stat.size as usize,
+            cid: Some(stat.hash.clone()),
         }
     }
 
-    fn from_entry(path: &str, entry: &FilesEntry) -> Self {
+    fn from_entry(path: &str, entry: &FilesEntry, times: Times) -> Self {
         Self {
             path: path.to_string(),
-            crtime: SystemTime::now(),
-            mtime: SystemTime::now(),
+            crtime: times.crtime,
+            mtime: times.mtime,
             is_dir: entry.typ == 1,
             size: entry.size as usize,
+            cid: Some(entry.hash.clone()),
+        }
+    }
+}
+
+/// Key of the IPFS node that IPNS records are signed with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerKey {
+    /// Local name of the key, `self` for the node's own.
+    pub name: String,
+
+    /// IPNS name the key publishes under.
+    pub id: String,
+}
+
+/// How files and directories written to MFS are turned into DAGs, `None` keeping
+/// the node's default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DagOptions {
+    /// Chunking algorithm of imported files, e.g. `size-1048576` or
+    /// `rabin-262144-524288-1048576`. `files/write` always uses the node's default.
+    pub chunker: Option<String>,
+
+    /// Whether leaf nodes hold the raw data instead of UnixFS wrapped blocks.
+    pub raw_leaves: Option<bool>,
+
+    /// CID version of the created blocks.
+    pub cid_version: Option<u32>,
+
+    /// Hash function of the created blocks, e.g. `sha2-256` or `blake3`.
+    pub hash: Option<String>,
+}
+
+impl DagOptions {
+    /// Takes the options that are set here and the remaining ones from `base`.
+    pub fn or(self, base: &DagOptions) -> DagOptions {
+        DagOptions {
+            chunker: self.chunker.or_else(|| base.chunker.clone()),
+            raw_leaves: self.raw_leaves.or(base.raw_leaves),
+            cid_version: self.cid_version.or(base.cid_version),
+            hash: self.hash.or_else(|| base.hash.clone()),
         }
     }
+
+    /// Whether the options change how directory CIDs are built.
+    pub fn sets_cid(&self) -> bool {
+        self.cid_version.is_some() || self.hash.is_some()
+    }
 }
 
 /// The default implemented API for interfacing with the IPFS RPC API.
@@ -111,8 +376,13 @@ impl PeerEntry {
 /// To change or enhance any of the functionality of interfacing with the IPFS RPC API,
 /// users of `ipfs-webdav` need to implement the `PeerApi` trait for their implementation
 /// of an API that interfaces with the IPFS PRC API.
+///
+/// Modification times are taken from the node when it reports UnixFS 1.5 metadata.
+/// Otherwise, as well as for creation times which MFS doesn't keep at all, they come
+/// from a timestamp store that can be persisted with `with_time_store`.
 pub struct BaseApi {
     ipfs: IpfsClient,
+    times: TimeStore,
 }
 
 impl BaseApi {
@@ -128,7 +398,37 @@ impl BaseApi {
 
     /// Creates a new instance of `BaseApi` from provided `IpfsClient`
     pub fn from_ipfs_client(ipfs: IpfsClient) -> Box<BaseApi> {
-        Box::new(BaseApi { ipfs })
+        Box::new(BaseApi {
+            ipfs,
+            times: TimeStore::default(),
+        })
+    }
+
+    /// Persists timestamps the node doesn't keep in the given local file
+    pub fn with_time_store(mut self: Box<Self>, file: impl Into<PathBuf>) -> Box<BaseApi> {
+        self.times = TimeStore::open(file.into());
+        self
+    }
+
+    // Prefers the modification time reported by the node over the stored one,
+    // paths that were never written here fall back to the epoch.
+    fn times(&self, path: &str, secs: Option<i64>, nsecs: Option<u32>) -> Times {
+        let stored = self.times.get(path);
+        match (rpc::to_system_time(secs, nsecs), stored) {
+            (Some(mtime), Some(times)) => Times {
+                crtime: times.crtime.min(mtime),
+                mtime,
+            },
+            (Some(mtime), None) => Times {
+                crtime: mtime,
+                mtime,
+            },
+            (None, Some(times)) => times,
+            (None, None) => Times {
+                crtime: UNIX_EPOCH,
+                mtime: UNIX_EPOCH,
+            },
+        }
     }
 }
 
@@ -140,69 +440,109 @@ impl Debug for BaseApi {
 
 #[async_trait]
 impl PeerApi for BaseApi {
-    async fn cp(&self, path: &str, dest: &str) -> Result<(), Error> {
+    async fn cp(&self, path: &str, dest: &str) -> Result<(), PeerError> {
         let path = normalize_path(path);
         let dest = normalize_path(dest);
-        self.ipfs.files_cp(&path, &dest).await
+        let req = FilesCp {
+            path: &path,
+            dest: &dest,
+            flush: Some(false),
+        };
+        self.ipfs.files_cp_with_options(req).await?;
+        self.times.cp(&path, &dest);
+        self.times.save();
+        Ok(())
     }
 
-    async fn flush(&self, path: &str) -> Result<(), Error> {
+    async fn flush(&self, path: &str) -> Result<(), PeerError> {
         let path = normalize_path(path);
-        self.ipfs.files_flush(Some(&path)).await
+        self.ipfs.files_flush(Some(&path)).await?;
+        self.times.save();
+        Ok(())
     }
 
-    async fn ls(&self, path: &str) -> Result<Vec<PeerEntry>, Error> {
+    async fn ls(&self, path: &str) -> Result<Vec<PeerEntry>, PeerError> {
         let path = normalize_path(path);
-        let req = FilesLs {
-            path: Some(&path),
-            long: Some(true),
-            ..Default::default()
+        let req = rpc::FilesLs {
+            path: &path,
+            long: true,
         };
-        let res = self.ipfs.files_ls_with_options(req).await?;
-        Ok(res
+        let res: rpc::FilesLsResponse = self.ipfs.request(req, None).await?;
+        let entries = res
             .entries
             .iter()
-            .map(|e| PeerEntry::from_entry(&concat_path(&path, &e.name), e))
-            .collect())
+            .map(|e| {
+                let path = concat_path(&path, &e.name);
+                let times = self.times(&path, e.mtime, e.mtime_nsecs);
+                PeerEntry::from_entry(&path, e, times)
+            })
+            .collect();
+        Ok(entries)
     }
 
-    async fn mkdir(&self, path: &str) -> Result<PeerEntry, Error> {
-        let path = normalize_path(path);
-        self.ipfs.files_mkdir(&path, false).await?;
-        Ok(PeerEntry::new_dir(&path))
+    async fn mkdir(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        self.mkdir_with(path, &DagOptions::default()).await
     }
 
-    async fn mv(&self, path: &str, dest: &str) -> Result<(), Error> {
+    async fn mv(&self, path: &str, dest: &str) -> Result<(), PeerError> {
         let path = normalize_path(path);
         let dest = normalize_path(dest);
-        self.ipfs.files_mv(&path, &dest).await
+        let req = FilesMv {
+            path: &path,
+            dest: &dest,
+            flush: Some(false),
+        };
+        self.ipfs.files_mv_with_options(req).await?;
+        self.times.mv(&path, &dest);
+        self.times.save();
+        Ok(())
     }
 
-    async fn read(&self, path: &str, offset: usize, count: usize) -> Result<Bytes, Error> {
+    async fn read(&self, path: &str, offset: usize, count: usize) -> Result<Bytes, PeerError> {
         let path = normalize_path(path);
         let req = FilesRead {
             path: &path,
             offset: Some(offset as i64),
             count: Some(count as i64),
         };
-        let data = self
-            .ipfs
-            .files_read_with_options(req)
-            .map_ok(|chunk| chunk.to_vec())
-            .try_concat()
-            .await?;
-        Ok(Bytes::copy_from_slice(&data))
+        let mut chunks = self.ipfs.files_read_with_options(req);
+        let mut data = BytesMut::new();
+        while let Some(chunk) = chunks.try_next().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data.freeze())
+    }
+
+    async fn read_stream(&self, path: &str, offset: usize) -> Result<PeerStream, PeerError> {
+        let path = normalize_path(path);
+        let req = FilesRead {
+            path: &path,
+            offset: Some(offset as i64),
+            count: None,
+        };
+        let chunks = self.ipfs.files_read_with_options(req);
+        Ok(chunks.map_err(PeerError::from).boxed())
     }
 
-    async fn rm(&self, path: &str) -> Result<(), Error> {
+    async fn rm(&self, path: &str) -> Result<(), PeerError> {
         let path = normalize_path(path);
-        self.ipfs.files_rm(&path, true).await
+        let req = FilesRm {
+            path: &path,
+            recursive: Some(true),
+            flush: Some(false),
+        };
+        self.ipfs.files_rm_with_options(req).await?;
+        self.times.rm(&path);
+        self.times.save();
+        Ok(())
     }
 
-    async fn stat(&self, path: &str) -> Result<PeerEntry, Error> {
+    async fn stat(&self, path: &str) -> Result<PeerEntry, PeerError> {
         let path = normalize_path(path);
-        let stat = self.ipfs.files_stat(&path).await?;
-        Ok(PeerEntry::from_stat(&path, &stat))
+        let req = rpc::FilesStat { path: &path };
+        let stat: FilesStatResponse = self.ipfs.request(req, None).await?;
+        let times = self.times(&path, stat.mtime, stat.mtime_nsecs);
+        Ok(PeerEntry::from_stat(&path, &stat, times))
     }
 
     async fn write(
@@ -211,17 +551,236 @@ impl PeerApi for BaseApi {
         offset: usize,
         truncate: bool,
         data: Bytes,
-    ) -> Result<(), Error> {
+    ) -> Result<(), PeerError> {
+        let path = normalize_path(path);
+        let req = FilesWrite {
+            path: &path,
+            offset: Some(offset as i64),
+            create: Some(true),
+            truncate: Some(truncate),
+            flush: Some(false),
+            ..Default::default()
+        };
+        self.ipfs
+            .files_write_with_options(req, data.reader())
+            .await?;
+        self.times.touch(&path, SystemTime::now());
+        Ok(())
+    }
+
+    async fn write_from(
+        &self,
+        path: &str,
+        offset: usize,
+        truncate: bool,
+        data: PeerBody,
+        options: &DagOptions,
+    ) -> Result<(), PeerError> {
         let path = normalize_path(path);
         let req = FilesWrite {
             path: &path,
             offset: Some(offset as i64),
             create: Some(true),
             truncate: Some(truncate),
+            raw_leaves: options.raw_leaves,
+            hash: options.hash.as_deref(),
+            cid_version: options.cid_version.map(|v| v as i32),
+            flush: Some(false),
+            ..Default::default()
+        };
+        self.ipfs.files_write_with_options(req, data).await?;
+        self.times.touch(&path, SystemTime::now());
+        Ok(())
+    }
+
+    async fn add(&self, path: &str, data: PeerBody, options: &DagOptions) -> Result<(), PeerError> {
+        let path = normalize_path(path);
+        let req = Add {
+            chunker: options.chunker.as_deref(),
+            pin: Some(false),
+            raw_leaves: options.raw_leaves,
+            cid_version: options.cid_version,
+            hash: options.hash.as_deref(),
+            ..Default::default()
+        };
+        let added = self.ipfs.add_with_options(data, req).await?;
+        // files/cp refuses to replace the destination
+        let rm = FilesRm {
+            path: &path,
+            flush: Some(false),
+            ..Default::default()
+        };
+        match self
+            .ipfs
+            .files_rm_with_options(rm)
+            .await
+            .map_err(PeerError::from)
+        {
+            Ok(_) | Err(PeerError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        let src = format!("/ipfs/{}", added.hash);
+        let cp = FilesCp {
+            path: &src,
+            dest: &path,
+            flush: Some(false),
+        };
+        self.ipfs.files_cp_with_options(cp).await?;
+        self.times.touch(&path, SystemTime::now());
+        Ok(())
+    }
+
+    async fn mkdir_with(&self, path: &str, options: &DagOptions) -> Result<PeerEntry, PeerError> {
+        let path = normalize_path(path);
+        let req = FilesMkdir {
+            path: &path,
+            hash: options.hash.as_deref(),
+            cid_version: options.cid_version.map(|v| v as i32),
             flush: Some(false),
             ..Default::default()
         };
-        self.ipfs.files_write_with_options(req, data.reader()).await
+        self.ipfs.files_mkdir_with_options(req).await?;
+        self.times.touch(&path, SystemTime::now());
+        self.stat(&path).await
+    }
+
+    async fn chcid(&self, path: &str, options: &DagOptions) -> Result<(), PeerError> {
+        if !options.sets_cid() {
+            return Ok(());
+        }
+        let path = normalize_path(path);
+        let req = FilesChcid {
+            path: Some(&path),
+            hash: options.hash.as_deref(),
+            cid_version: options.cid_version.map(|v| v as i32),
+            flush: Some(false),
+        };
+        self.ipfs.files_chcid_with_options(req).await?;
+        Ok(())
+    }
+
+    async fn touch(&self, path: &str, mtime: SystemTime) -> Result<(), PeerError> {
+        let path = normalize_path(path);
+        let (secs, nsecs) = rpc::from_system_time(mtime);
+        let req = rpc::FilesTouch {
+            path: &path,
+            mtime: secs,
+            mtime_nsecs: nsecs,
+            // flushing is up to the caller
+            flush: false,
+        };
+        match self.ipfs.request_empty(req, None).await {
+            Ok(_) => {}
+            // nodes predating UnixFS 1.5 don't know the command; the store keeps the time
+            Err(Error::IpfsClientError(ipfs_api_prelude::Error::UnrecognizedApiError(m)))
+                if m.contains("404") => {}
+            Err(Error::Api(e)) if e.message.contains("unknown command") => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.times.touch(&path, mtime);
+        self.times.save();
+        Ok(())
+    }
+
+    async fn ipfs_ls(&self, path: &str) -> Result<Vec<PeerEntry>, PeerError> {
+        let path = normalize_path(path);
+        let res = self.ipfs.ls(&path).await?;
+        let entries = res
+            .objects
+            .iter()
+            .flat_map(|o| o.links.iter())
+            .map(|link| PeerEntry {
+                path: concat_path(&path, &link.name),
+                // the node doesn't report times of linked content
+                crtime: UNIX_EPOCH,
+                mtime: UNIX_EPOCH,
+                is_dir: link.typ == 1,
+                size: link.size as usize,
+                cid: Some(link.hash.clone()),
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    async fn ipfs_stat(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        let path = normalize_path(path);
+        let req = rpc::FilesStat { path: &path };
+        let stat: FilesStatResponse = self.ipfs.request(req, None).await?;
+        let mtime = rpc::to_system_time(stat.mtime, stat.mtime_nsecs).unwrap_or(UNIX_EPOCH);
+        let times = Times {
+            crtime: mtime,
+            mtime,
+        };
+        Ok(PeerEntry::from_stat(&path, &stat, times))
+    }
+
+    async fn cat(&self, path: &str, offset: usize) -> Result<PeerStream, PeerError> {
+        let path = normalize_path(path);
+        let chunks = self.ipfs.cat_range(&path, offset, i64::MAX as usize);
+        Ok(chunks.map_err(PeerError::from).boxed())
+    }
+
+    async fn resolve_name(&self, name: &str) -> Result<String, PeerError> {
+        let res = self.ipfs.name_resolve(Some(name), true, false).await?;
+        Ok(res.path)
+    }
+
+    async fn key_list(&self) -> Result<Vec<PeerKey>, PeerError> {
+        let res = self.ipfs.key_list().await?;
+        let keys = res
+            .keys
+            .into_iter()
+            .map(|k| PeerKey {
+                name: k.name,
+                id: k.id,
+            })
+            .collect();
+        Ok(keys)
+    }
+
+    async fn name_publish(&self, path: &str, key: &str) -> Result<String, PeerError> {
+        let res = self
+            .ipfs
+            .name_publish(path, false, None, None, Some(key))
+            .await?;
+        Ok(res.name)
+    }
+}
+
+impl From<Error> for PeerError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Api(e) => from_message(e.message, e.code),
+            Error::Client(e) if e.is_connect() => PeerError::Unavailable,
+            Error::Client(e) if e.is_timeout() => PeerError::Timeout,
+            e => from_message(e.to_string(), 0),
+        }
+    }
+}
+
+// Kubo reports failures as free-form messages, so classify them by their wording.
+fn from_message(message: String, code: u8) -> PeerError {
+    let m = message.to_lowercase();
+    if m.contains("does not exist")
+        || m.contains("not found")
+        || m.contains("no link named")
+        || m.contains("no such file")
+    {
+        PeerError::NotFound
+    } else if m.contains("already exists") || m.contains("already has entry") {
+        PeerError::AlreadyExists
+    } else if m.contains("not empty") || m.contains("use -r") {
+        PeerError::NotEmpty
+    } else if m.contains("permission denied") || m.contains("not a directory") {
+        PeerError::PermissionDenied
+    } else if m.contains("no space left") || m.contains("quota") {
+        PeerError::NoSpace
+    } else if m.contains("connection refused") || m.contains("connection reset") {
+        PeerError::Unavailable
+    } else if m.contains("timed out") || m.contains("deadline exceeded") {
+        PeerError::Timeout
+    } else {
+        PeerError::other(format!("[{}] {}", code, message))
     }
 }
 
@@ -0,0 +1,127 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::fs::File;
+use std::io::{self, Cursor, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::PathBuf;
+
+use crate::api::PeerBody;
+
+/// How uploads are collected before they are written to MFS.
+///
+/// webdav-handler hands a request body over in small chunks. Instead of one
+/// `files/write` per chunk, consecutive chunks are kept in memory up to
+/// `buffer_size` bytes, then spooled to a temporary file, and written to MFS in
+/// one go when the file is flushed or written out of order.
+///
+/// With `atomic` set, a file that is created or replaced is written to a hidden
+/// staging path and only moved over its destination once the upload is complete,
+/// so readers never see a partially written file.
+///
+/// With `add` set, a file that is written from scratch is imported with
+/// `PeerApi::add`, which gives it the same DAG and CID as `ipfs add` would.
+#[derive(Debug, Clone)]
+pub struct WritePolicy {
+    /// Bytes kept in memory before an upload is spooled to disk.
+    pub buffer_size: usize,
+
+    /// Directory of the spool files, `None` for the system temporary directory.
+    pub spool_dir: Option<PathBuf>,
+
+    /// Whether new and replaced files are staged until they are flushed.
+    pub atomic: bool,
+
+    /// Whether files written from scratch are imported with `ipfs add`.
+    ///
+    /// Their modification time is not stored on the node, so their CID is the
+    /// one `ipfs add` reports for the same content.
+    pub add: bool,
+}
+
+impl Default for WritePolicy {
+    fn default() -> Self {
+        WritePolicy {
+            buffer_size: 8 * 1024 * 1024,
+            spool_dir: None,
+            atomic: false,
+            add: false,
+        }
+    }
+}
+
+/// Consecutive bytes written to a file that have not reached MFS yet.
+#[derive(Debug)]
+pub(super) struct WriteBuffer {
+    policy: WritePolicy,
+    offset: usize,
+    len: usize,
+    memory: Vec<u8>,
+    spool: Option<File>,
+}
+
+impl WriteBuffer {
+    pub(super) fn new(policy: WritePolicy) -> Self {
+        WriteBuffer {
+            policy,
+            offset: 0,
+            len: 0,
+            memory: Vec::new(),
+            spool: None,
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// File offset right after the buffered bytes.
+    pub(super) fn end(&self) -> usize {
+        self.offset + self.len
+    }
+
+    /// Appends bytes written at `pos`, which has to be `end()` unless the buffer is empty.
+    pub(super) fn push(&mut self, pos: usize, data: &[u8]) -> io::Result<()> {
+        if self.is_empty() {
+            self.offset = pos;
+        }
+        debug_assert_eq!(pos, self.end());
+        match &mut self.spool {
+            Some(file) => file.write_all(data)?,
+            None if self.len + data.len() > self.policy.buffer_size => {
+                let mut file = match &self.policy.spool_dir {
+                    Some(dir) => tempfile::tempfile_in(dir)?,
+                    None => tempfile::tempfile()?,
+                };
+                file.write_all(&self.memory)?;
+                file.write_all(data)?;
+                self.memory = Vec::new();
+                self.spool = Some(file);
+            }
+            None => self.memory.extend_from_slice(data),
+        }
+        self.len += data.len();
+        Ok(())
+    }
+
+    /// Hands the buffered bytes over along with their offset, leaving the buffer empty.
+    pub(super) fn take(&mut self) -> io::Result<Option<(usize, PeerBody)>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let body: PeerBody = match self.spool.take() {
+            Some(mut file) => {
+                file.seek(SeekFrom::Start(0))?;
+                Box::new(file)
+            }
+            None => Box::new(Cursor::new(mem::take(&mut self.memory))),
+        };
+        self.len = 0;
+        Ok(Some((self.offset, body)))
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use http::request::Parts;
+use http::Response;
+use webdav_handler::body::Body;
+use webdav_handler::ls::DavLockSystem;
+use webdav_handler::memls::MemLs;
+use webdav_handler::{DavHandler, DavMethodSet};
+
+use crate::api::{DagOptions, PeerApi};
+use crate::buffer::WritePolicy;
+use crate::cache::{Cache, CachePolicy, CacheStats};
+use crate::dag::DagSettings;
+use crate::error::BuildError;
+use crate::flush::{FlushPolicy, Flusher};
+use crate::fs::PeerFs;
+use crate::immutable::Immutable;
+use crate::readonly::ReadOnly;
+use crate::root::ShareRoot;
+use crate::server::{AuthHook, IpfsWebDav};
+
+/// Builder for a WebDAV handler serving the MFS of an IPFS node.
+pub struct IpfsWebDavBuilder {
+    api: Arc<Box<dyn PeerApi>>,
+    cache_policy: CachePolicy,
+    cache_stats: CacheStats,
+    write_policy: WritePolicy,
+    dag_options: DagOptions,
+    collection_dag_options: BTreeMap<String, DagOptions>,
+    read_ahead: usize,
+    flush_policy: FlushPolicy,
+    flusher: Flusher,
+    locksystem: Box<dyn DavLockSystem>,
+    prefix: String,
+    root: String,
+    read_only: bool,
+    read_only_collections: Vec<String>,
+    immutable: bool,
+    immutable_mounts: [String; 2],
+    auth: Option<AuthHook>,
+}
+
+impl IpfsWebDavBuilder {
+    /// Creates a builder with default settings on top of the given API.
+    pub fn new(api: Box<dyn PeerApi>) -> Self {
+        let api = Arc::new(api);
+        IpfsWebDavBuilder {
+            api: api.clone(),
+            cache_policy: CachePolicy::default(),
+            cache_stats: CacheStats::default(),
+            write_policy: WritePolicy::default(),
+            dag_options: DagOptions::default(),
+            collection_dag_options: BTreeMap::new(),
+            read_ahead: 16,
+            flush_policy: FlushPolicy::default(),
+            flusher: Flusher::new(api),
+            locksystem: MemLs::new(),
+            prefix: String::new(),
+            root: "/".to_string(),
+            read_only: false,
+            read_only_collections: Vec::new(),
+            immutable: false,
+            immutable_mounts: ["ipfs".to_string(), "ipns".to_string()],
+            auth: None,
+        }
+    }
+
+    /// Sets how long cached MFS metadata is trusted.
+    pub fn cache_policy(mut self, policy: CachePolicy) -> Self {
+        self.cache_policy = policy;
+        self
+    }
+
+    /// Returns the counters the built handler's cache will update.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache_stats.clone()
+    }
+
+    /// Sets how uploads are buffered before they are written to MFS.
+    pub fn write_policy(mut self, policy: WritePolicy) -> Self {
+        self.write_policy = policy;
+        self
+    }
+
+    /// Sets the CID version, hash function, raw leaves and chunker of everything written.
+    pub fn dag_options(mut self, options: DagOptions) -> Self {
+        self.dag_options = options;
+        self
+    }
+
+    /// Overrides the DAG options below a collection of the share, for the options it sets.
+    pub fn collection_dag_options(mut self, path: &str, options: DagOptions) -> Self {
+        self.collection_dag_options
+            .insert(path.to_string(), options);
+        self
+    }
+
+    /// Sets how many chunks of a file are fetched ahead of a sequential reader.
+    pub fn read_ahead(mut self, chunks: usize) -> Self {
+        self.read_ahead = chunks;
+        self
+    }
+
+    /// Sets when uploads are flushed to the node's blockstore.
+    pub fn flush_policy(mut self, policy: FlushPolicy) -> Self {
+        self.flush_policy = policy;
+        self
+    }
+
+    /// Returns the handle that flushes what the built handler left pending.
+    pub fn flusher(&self) -> Flusher {
+        self.flusher.clone()
+    }
+
+    /// Sets the lock system, `MemLs` by default.
+    pub fn locksystem(mut self, locksystem: Box<dyn DavLockSystem>) -> Self {
+        self.locksystem = locksystem;
+        self
+    }
+
+    /// Sets the URL path the share is mounted at, which is stripped from requests.
+    pub fn strip_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Sets the MFS directory served as the root of the share, `/` by default.
+    ///
+    /// The directory is created on build if it does not exist.
+    pub fn root(mut self, path: &str) -> Self {
+        self.root = path.to_string();
+        self
+    }
+
+    /// Sets whether the whole share is read-only, which leaves only the methods
+    /// that read it in `OPTIONS` and `Allow`. A read-only share never changes
+    /// MFS, so its root is not created either.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Makes everything below a collection of the share read-only, changes to it
+    /// are answered with `403 Forbidden`.
+    pub fn read_only_collection(mut self, path: &str) -> Self {
+        self.read_only_collections.push(path.to_string());
+        self
+    }
+
+    /// Sets whether content outside MFS can be browsed below `/ipfs/<cid>` and
+    /// `/ipns/<name>`, read-only, and copied into MFS from there.
+    ///
+    /// The two directories take the place of MFS entries of the same name at
+    /// the root of the share, see `immutable_mounts` to name them differently.
+    pub fn immutable_paths(mut self, enabled: bool) -> Self {
+        self.immutable = enabled;
+        self
+    }
+
+    /// Sets the names of the directories `/ipfs` and `/ipns` are shown as at
+    /// the root of the share, e.g. `.ipfs` and `.ipns` to keep them clear of MFS.
+    pub fn immutable_mounts(mut self, ipfs: &str, ipns: &str) -> Self {
+        self.immutable_mounts = [ipfs.to_string(), ipns.to_string()];
+        self
+    }
+
+    /// Sets the hook that authenticates requests, see `AuthHook`.
+    pub fn auth<F>(mut self, hook: F) -> Self
+    where
+        F: for<'a> Fn(&'a Parts) -> BoxFuture<'a, Result<Option<String>, Box<Response<Body>>>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.auth = Some(Arc::new(hook));
+        self
+    }
+
+    /// Creates the server, which runs the auth hook, if any, in front of the
+    /// WebDAV handler.
+    ///
+    /// # Errors
+    ///
+    /// If a configured path has `..` segments or a mount name is not a single segment.
+    pub fn build(self) -> Result<IpfsWebDav, BuildError> {
+        let root = ShareRoot::new(&self.root)?;
+        let dag = DagSettings::new(self.dag_options, self.collection_dag_options, &root)?;
+        let read_only = ReadOnly::new(self.read_only, self.read_only_collections, &root)?;
+        let immutable = Immutable::new(self.immutable, self.immutable_mounts)?;
+        let methods = if self.read_only {
+            DavMethodSet::WEBDAV_RO
+        } else {
+            DavMethodSet::WEBDAV_RW
+        };
+        let fs = PeerFs::new(
+            self.api,
+            Cache::new(self.cache_policy, self.cache_stats),
+            root,
+            read_only,
+            immutable,
+            self.write_policy,
+            dag,
+            self.read_ahead,
+            self.flush_policy,
+            self.flusher,
+        );
+        let handler = DavHandler::builder()
+            .filesystem(fs.clone())
+            .locksystem(self.locksystem)
+            .strip_prefix(self.prefix.clone())
+            .methods(methods)
+            .build_handler();
+        Ok(IpfsWebDav::new(handler, fs, self.prefix, self.auth))
+    }
+}
@@ -6,78 +6,376 @@
 // copied, modified, or distributed except according to those terms.
 //
 
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeMap, HashSet};
+use std::mem;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use webdav_handler::fs::FsError;
 
 use crate::fs::PeerNode;
 
-#[derive(Default, Debug, Clone)]
+/// How long cached MFS metadata is trusted, and how much of it is kept.
+///
+/// MFS can change behind the server's back, through the `ipfs files` CLI, the
+/// web UI or another server instance. Entries older than `ttl` are fetched
+/// again before they are used, and with `revalidate` set the CID of the MFS
+/// root is compared at that interval, dropping the whole cache when it moved.
+///
+/// The cache is bounded by `max_entries` and `max_bytes`, evicting the least
+/// recently used entries first. Evicted entries are simply fetched again.
+#[derive(Debug, Clone, Copy)]
+pub struct CachePolicy {
+    /// Age after which an entry is fetched again, `None` to keep it until it is
+    /// changed through WebDAV.
+    pub ttl: Option<Duration>,
+
+    /// Interval of root CID checks, `None` to never check.
+    pub revalidate: Option<Duration>,
+
+    /// Number of entries above which the least recently used ones are dropped.
+    pub max_entries: Option<usize>,
+
+    /// Approximate memory in bytes above which the least recently used entries
+    /// are dropped.
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        CachePolicy {
+            ttl: Some(Duration::from_secs(5)),
+            revalidate: None,
+            max_entries: Some(100_000),
+            max_bytes: None,
+        }
+    }
+}
+
+/// Counters of a handler's metadata cache, shared with the handler that updates them.
+#[derive(Debug, Clone, Default)]
+pub struct CacheStats {
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    entries: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Default)]
 pub(super) struct Cache {
-    cache: Arc<RwLock<HashMap<String, PeerNode>>>,
+    inner: Arc<Mutex<Inner>>,
+    root: Arc<Mutex<RootCheck>>,
+    policy: CachePolicy,
+    stats: CacheStats,
+}
+
+// Entries are sorted by path, so a subtree is a contiguous range of keys, and
+// ordered by their last use through `lru`, which maps a use tick to its key.
+#[derive(Debug, Default)]
+struct Inner {
+    entries: BTreeMap<String, Entry>,
+    lru: BTreeMap<u64, String>,
+    tick: u64,
+    bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    node: PeerNode,
+    fetched: Instant,
+    used: u64,
+    size: usize,
+}
+
+#[derive(Debug, Default)]
+struct RootCheck {
+    cid: Option<String>,
+    checked: Option<Instant>,
+}
+
+impl CacheStats {
+    /// Lookups answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.counters.hits.load(Ordering::Relaxed)
+    }
+
+    /// Lookups that had to go to the IPFS node.
+    pub fn misses(&self) -> u64 {
+        self.counters.misses.load(Ordering::Relaxed)
+    }
+
+    /// Entries dropped to stay within the limits.
+    pub fn evictions(&self) -> u64 {
+        self.counters.evictions.load(Ordering::Relaxed)
+    }
+
+    /// Entries currently cached.
+    pub fn entries(&self) -> usize {
+        self.counters.entries.load(Ordering::Relaxed)
+    }
+
+    /// Approximate memory held by the cached entries.
+    pub fn bytes(&self) -> usize {
+        self.counters.bytes.load(Ordering::Relaxed)
+    }
+
+    fn lookup(&self, hit: bool) {
+        let counter = match hit {
+            true => &self.counters.hits,
+            false => &self.counters.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 impl Cache {
-    pub(super) fn contains(&self, hash: &str) -> bool {
+    pub(super) fn new(policy: CachePolicy, stats: CacheStats) -> Self {
+        Cache {
+            policy,
+            stats,
+            ..Default::default()
+        }
+    }
+
+    /// Whether a path is cached and younger than the TTL.
+    pub(super) fn is_fresh(&self, hash: &str) -> bool {
         let hash = normalize_hash(hash);
-        let cache = self.cache.read().unwrap();
-        cache.contains_key(&hash)
+        let inner = &mut *self.inner.lock().unwrap();
+        let fresh = match (inner.touch(&hash), self.policy.ttl) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(entry), Some(ttl)) => entry.fetched.elapsed() < ttl,
+        };
+        self.stats.lookup(fresh);
+        fresh
     }
 
     pub(super) fn get(&self, hash: &str) -> Result<PeerNode, FsError> {
         let hash = normalize_hash(hash);
-        let cache = self.cache.read().unwrap();
-        match cache.get(&hash) {
-            None => Err(FsError::NotFound),
-            Some(value) => Ok(value.clone()),
-        }
+        let inner = &mut *self.inner.lock().unwrap();
+        let node = inner.touch(&hash).map(|entry| entry.node.clone());
+        self.stats.lookup(node.is_some());
+        node.ok_or(FsError::NotFound)
+    }
+
+    /// Whether the properties of a path are cached, which does not count as a lookup.
+    pub(super) fn has_props(&self, hash: &str) -> bool {
+        let inner = self.inner.lock().unwrap();
+        let entry = inner.entries.get(&normalize_hash(hash));
+        entry.is_some_and(|e| e.node.props().is_some())
     }
 
     pub(super) fn insert(&self, hash: &str, node: PeerNode) {
         let hash = normalize_hash(hash);
-        let cache = &mut self.cache.write().unwrap();
-        cache.insert(hash, node);
+        let inner = &mut *self.inner.lock().unwrap();
+        inner.put(hash, node, Instant::now());
+        self.evict(inner);
     }
 
+    /// Stores fresh metadata of a path, keeping what only the cache knows about it.
+    pub(super) fn update(&self, hash: &str, node: PeerNode) {
+        let hash = normalize_hash(hash);
+        let inner = &mut *self.inner.lock().unwrap();
+        let node = match inner.take(&hash) {
+            Some(mut entry) => {
+                entry.node.merge(node);
+                entry.node
+            }
+            None => node,
+        };
+        inner.put(hash, node, Instant::now());
+        self.evict(inner);
+    }
+
+    /// Removes a path and everything below it.
     pub(super) fn remove(&self, hash: &str) {
         let hash = normalize_hash(hash);
-        let cache = &mut self.cache.write().unwrap();
-        cache.remove(&hash);
+        let inner = &mut *self.inner.lock().unwrap();
+        inner.take_subtree(&hash);
+        self.record(inner);
+    }
+
+    /// Drops the children of a directory that a fresh listing no longer shows.
+    pub(super) fn retain_children(&self, hash: &str, names: &HashSet<String>) {
+        let prefix = add_slash(&normalize_hash(hash));
+        let inner = &mut *self.inner.lock().unwrap();
+        let keys: Vec<String> = inner
+            .subtree(&prefix)
+            .map(|(k, _)| k)
+            .filter(|k| !names.contains(k[prefix.len()..].split('/').next().unwrap()))
+            .cloned()
+            .collect();
+        for key in keys {
+            inner.take(&key);
+        }
+        self.record(inner);
+    }
+
+    /// Forgets the CIDs of all ancestors of a changed path.
+    pub(super) fn invalidate_cids(&self, hash: &str) {
+        let hash = normalize_hash(hash);
+        let inner = &mut *self.inner.lock().unwrap();
+        let mut path = Path::new(&hash);
+        while let Some(parent) = path.parent() {
+            let key = normalize_hash(parent.to_str().unwrap());
+            if let Some(entry) = inner.entries.get_mut(&key) {
+                entry.node.set_cid(None);
+            }
+            path = parent;
+        }
     }
 
+    /// Whether the root CID should be checked, which counts as checking it.
+    pub(super) fn revalidation_due(&self) -> bool {
+        let interval = match self.policy.revalidate {
+            Some(interval) => interval,
+            None => return false,
+        };
+        let root = &mut self.root.lock().unwrap();
+        match root.checked {
+            Some(checked) if checked.elapsed() < interval => false,
+            _ => {
+                root.checked = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    /// Drops everything cached if the root CID differs from the one seen last.
+    pub(super) fn revalidate(&self, cid: Option<&str>) {
+        let root = &mut self.root.lock().unwrap();
+        if root.cid.as_deref() != cid {
+            if root.cid.is_some() {
+                debug!("MFS root changed to {:?}, dropping cache", cid);
+                let inner = &mut *self.inner.lock().unwrap();
+                inner.clear();
+                self.record(inner);
+            }
+            root.cid = cid.map(|c| c.to_string());
+        }
+    }
+
+    /// Moves a path and everything below it, replacing whatever was cached at the destination.
     pub(super) fn mv_vals(&self, from: &str, to: &str) {
         let from = normalize_hash(from);
         let to = normalize_hash(to);
-        let prefix = add_slash(&from);
-        let cache = &mut *self.cache.write().unwrap();
-        cache
-            .clone()
-            .iter()
-            .filter(|&(k, _)| k == &from || k.starts_with(&prefix))
-            .for_each(|(k, _)| {
-                if let Some(v) = cache.remove(k) {
-                    let k = k.replace(from.as_str(), to.as_str());
-                    cache.insert(k, v);
-                }
-            });
+        let inner = &mut *self.inner.lock().unwrap();
+        let entries = inner.take_subtree(&from);
+        inner.take_subtree(&to);
+        for (k, v) in entries {
+            inner.put(rebase(&k, &from, &to), v.node, v.fetched);
+        }
+        self.evict(inner);
     }
 
+    /// Copies a path and everything below it, replacing whatever was cached at the destination.
     pub(super) fn cp_vals(&self, from: &str, to: &str) {
         let from = normalize_hash(from);
         let to = normalize_hash(to);
-        let prefix = add_slash(&from);
-        let cache = &mut *self.cache.write().unwrap();
-        cache
-            .clone()
-            .iter()
-            .filter(|&(k, _)| k == &from || k.starts_with(&prefix))
-            .for_each(|(k, _)| {
-                if let Some(v) = cache.get(k) {
-                    let k = k.replace(from.as_str(), to.as_str());
-                    cache.insert(k, v.clone());
-                }
-            });
+        let inner = &mut *self.inner.lock().unwrap();
+        let entries: Vec<(String, PeerNode, Instant)> = inner
+            .subtree(&from)
+            .map(|(k, v)| (rebase(k, &from, &to), v.node.clone(), v.fetched))
+            .collect();
+        inner.take_subtree(&to);
+        for (k, node, fetched) in entries {
+            inner.put(k, node, fetched);
+        }
+        self.evict(inner);
+    }
+
+    // Drops the least recently used entries until the cache is within its limits.
+    fn evict(&self, inner: &mut Inner) {
+        let max_entries = self.policy.max_entries.unwrap_or(usize::MAX);
+        let max_bytes = self.policy.max_bytes.unwrap_or(usize::MAX);
+        while inner.entries.len() > 1
+            && (inner.entries.len() > max_entries || inner.bytes > max_bytes)
+        {
+            let key = match inner.lru.values().next() {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            inner.take(&key);
+            self.stats
+                .counters
+                .evictions
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        self.record(inner);
+    }
+
+    fn record(&self, inner: &Inner) {
+        let counters = &self.stats.counters;
+        counters
+            .entries
+            .store(inner.entries.len(), Ordering::Relaxed);
+        counters.bytes.store(inner.bytes, Ordering::Relaxed);
+    }
+}
+
+impl Inner {
+    // Looks an entry up, marking it as the most recently used.
+    fn touch(&mut self, key: &str) -> Option<&Entry> {
+        let entry = self.entries.get_mut(key)?;
+        self.lru.remove(&entry.used);
+        self.tick += 1;
+        entry.used = self.tick;
+        self.lru.insert(self.tick, key.to_string());
+        Some(entry)
+    }
+
+    fn put(&mut self, key: String, node: PeerNode, fetched: Instant) {
+        self.take(&key);
+        self.tick += 1;
+        let size = key.len() + mem::size_of::<Entry>() + node.heap_size();
+        self.bytes += size;
+        self.lru.insert(self.tick, key.clone());
+        let entry = Entry {
+            node,
+            fetched,
+            used: self.tick,
+            size,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    fn take(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.lru.remove(&entry.used);
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+        self.bytes = 0;
+    }
+
+    // A path and everything below it.
+    fn subtree<'a>(&'a self, hash: &'a str) -> impl Iterator<Item = (&'a String, &'a Entry)> + 'a {
+        let prefix = add_slash(hash);
+        let below = self
+            .entries
+            .range::<str, _>((Bound::Included(prefix.as_str()), Bound::Unbounded))
+            .take_while(move |(k, _)| k.starts_with(&prefix));
+        self.entries.get_key_value(hash).into_iter().chain(below)
+    }
+
+    fn take_subtree(&mut self, hash: &str) -> Vec<(String, Entry)> {
+        let keys: Vec<String> = self.subtree(hash).map(|(k, _)| k.clone()).collect();
+        keys.into_iter()
+            .filter_map(|k| self.take(&k).map(|v| (k, v)))
+            .collect()
     }
 }
 
@@ -90,6 +388,12 @@ fn normalize_hash(hash: &str) -> String {
     hash
 }
 
+// Replaces the leading `from` of a key in the subtree of `from` with `to`.
+#[inline]
+fn rebase(key: &str, from: &str, to: &str) -> String {
+    format!("{}{}", to, &key[from.len()..])
+}
+
 #[inline]
 fn add_slash(hash: &str) -> String {
     let mut hash = hash.to_string();
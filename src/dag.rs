@@ -0,0 +1,92 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use tokio::sync::OnceCell;
+
+use crate::api::{DagOptions, PeerApi, PeerError};
+use crate::error::BuildError;
+use crate::root::ShareRoot;
+
+/// DAG options of the served tree, set for the whole server and overridden per collection.
+///
+/// A collection's options apply to everything below it, with the deepest collection
+/// winning for each option it sets. The CID settings of the share root and the
+/// collections, which may predate the configuration, are applied with `files/chcid`
+/// before the first change.
+#[derive(Debug, Clone, Default)]
+pub(super) struct DagSettings {
+    server: DagOptions,
+    root: String,
+    collections: BTreeMap<String, DagOptions>,
+    applied: Arc<OnceCell<()>>,
+}
+
+impl DagSettings {
+    /// Creates the settings for collections given as paths of the share.
+    pub(super) fn new(
+        server: DagOptions,
+        collections: BTreeMap<String, DagOptions>,
+        root: &ShareRoot,
+    ) -> Result<Self, BuildError> {
+        let collections = collections
+            .into_iter()
+            .map(|(path, options)| Ok((root.join(&path)?, options)))
+            .collect::<Result<_, BuildError>>()?;
+        Ok(DagSettings {
+            server,
+            root: root.path().to_string(),
+            collections,
+            applied: Arc::default(),
+        })
+    }
+
+    /// Options for a path, merged from the server and all collections containing it.
+    pub(super) fn for_path(&self, path: &str) -> DagOptions {
+        // ancestors sort before their descendants, so deeper collections come later
+        self.collections
+            .iter()
+            .filter(|(dir, _)| is_within(path, dir))
+            .fold(self.server.clone(), |options, (_, o)| {
+                o.clone().or(&options)
+            })
+    }
+
+    /// Brings the CIDs of the root and the collections in line with their settings, once.
+    pub(super) async fn apply(&self, api: &dyn PeerApi) -> Result<(), PeerError> {
+        self.applied
+            .get_or_try_init(|| async {
+                let collections = self.collections.keys().filter(|k| **k != self.root);
+                let dirs =
+                    std::iter::once(self.root.as_str()).chain(collections.map(|k| k.as_str()));
+                for dir in dirs {
+                    let options = self.for_path(dir);
+                    if !options.sets_cid() {
+                        continue;
+                    }
+                    match api.chcid(dir, &options).await {
+                        Ok(_) | Err(PeerError::NotFound) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[inline]
+pub(super) fn is_within(path: &str, dir: &str) -> bool {
+    dir == "/"
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
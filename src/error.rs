@@ -0,0 +1,112 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use webdav_handler::fs::FsError;
+
+/// Error returned by `IpfsWebDavBuilder::build` for settings it cannot serve.
+#[derive(Debug)]
+pub enum BuildError {
+    /// A configured path has `..` segments, which could leave the share.
+    InvalidPath(String),
+
+    /// A mount name is empty or not a single path segment.
+    InvalidMount(String),
+}
+
+/// Error returned by `PeerApi` implementations.
+#[derive(Debug)]
+pub enum PeerError {
+    /// The MFS entity does not exist.
+    NotFound,
+
+    /// An MFS entity already exists at the destination.
+    AlreadyExists,
+
+    /// The directory still has entries.
+    NotEmpty,
+
+    /// The IPFS node refused the operation.
+    PermissionDenied,
+
+    /// The IPFS node has run out of storage.
+    NoSpace,
+
+    /// The IPFS node could not be reached.
+    Unavailable,
+
+    /// The IPFS node did not answer in time.
+    Timeout,
+
+    /// Any other failure, with the underlying error as its source.
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+impl PeerError {
+    /// Wraps an arbitrary error into `PeerError::Other`.
+    pub fn other<E>(err: E) -> Self
+    where
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        PeerError::Other(err.into())
+    }
+}
+
+impl Display for PeerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeerError::NotFound => write!(f, "file does not exist"),
+            PeerError::AlreadyExists => write!(f, "file already exists"),
+            PeerError::NotEmpty => write!(f, "directory not empty"),
+            PeerError::PermissionDenied => write!(f, "permission denied"),
+            PeerError::NoSpace => write!(f, "no space left on device"),
+            PeerError::Unavailable => write!(f, "ipfs node unavailable"),
+            PeerError::Timeout => write!(f, "ipfs node timed out"),
+            PeerError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for PeerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PeerError::Other(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::InvalidPath(path) => write!(f, "path {} must not contain ..", path),
+            BuildError::InvalidMount(name) => {
+                write!(f, "mount name {:?} is not a single segment", name)
+            }
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+impl From<PeerError> for FsError {
+    fn from(err: PeerError) -> Self {
+        let fs_err = match &err {
+            PeerError::NotFound => FsError::NotFound,
+            PeerError::AlreadyExists => FsError::Exists,
+            PeerError::NotEmpty | PeerError::PermissionDenied => FsError::Forbidden,
+            PeerError::NoSpace => FsError::InsufficientStorage,
+            PeerError::Unavailable | PeerError::Timeout => FsError::IsRemote,
+            PeerError::Other(_) => FsError::GeneralFailure,
+        };
+        debug!("Peer error mapped to {:?}: {}", fs_err, err);
+        fs_err
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use crate::api::{PeerApi, PeerError};
+
+/// When uploaded data is flushed from MFS to the node's blockstore.
+///
+/// A flush recomputes the DAG up to the MFS root, which dominates the cost of
+/// small uploads. Until it happens the changes only live in the node's memory,
+/// so the less often it runs, the more a crash of the node can lose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// After every write that reaches MFS.
+    Write,
+
+    /// When an upload is complete.
+    #[default]
+    Close,
+
+    /// At most once per interval, if anything was uploaded.
+    Periodic(Duration),
+
+    /// Only when `Flusher::flush` is called.
+    Manual,
+}
+
+/// Flushes the uploads of a handler that are still pending under its `FlushPolicy`.
+#[derive(Debug, Clone)]
+pub struct Flusher {
+    inner: Arc<FlushState>,
+}
+
+#[derive(Debug)]
+struct FlushState {
+    api: Arc<Box<dyn PeerApi>>,
+    pending: AtomicBool,
+    ticking: AtomicBool,
+}
+
+impl Flusher {
+    pub(super) fn new(api: Arc<Box<dyn PeerApi>>) -> Self {
+        Flusher {
+            inner: Arc::new(FlushState {
+                api,
+                pending: AtomicBool::new(false),
+                ticking: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Flushes the whole MFS if anything was uploaded since the last flush.
+    pub async fn flush(&self) -> Result<(), PeerError> {
+        if self.inner.pending.swap(false, Ordering::AcqRel) {
+            if let Err(e) = self.inner.api.flush("/").await {
+                self.inner.pending.store(true, Ordering::Release);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether uploaded data is waiting for a flush.
+    pub fn is_pending(&self) -> bool {
+        self.inner.pending.load(Ordering::Acquire)
+    }
+
+    /// Applies the policy to a write that reached MFS.
+    pub(super) async fn written(&self, path: &str, policy: FlushPolicy) -> Result<(), PeerError> {
+        match policy {
+            FlushPolicy::Write => self.inner.api.flush(path).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the policy to a complete upload.
+    pub(super) async fn closed(&self, path: &str, policy: FlushPolicy) -> Result<(), PeerError> {
+        match policy {
+            FlushPolicy::Write | FlushPolicy::Close => return self.inner.api.flush(path).await,
+            FlushPolicy::Periodic(interval) => self.schedule(interval),
+            FlushPolicy::Manual => {}
+        }
+        self.inner.pending.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    // Starts the periodic flush, which ends along with the handler.
+    fn schedule(&self, interval: Duration) {
+        if self.inner.ticking.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => runtime,
+            Err(_) => return,
+        };
+        let state: Weak<FlushState> = Arc::downgrade(&self.inner);
+        runtime.spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let flusher = match state.upgrade() {
+                    Some(inner) => Flusher { inner },
+                    None => return,
+                };
+                if let Err(e) = flusher.flush().await {
+                    warn!("Periodic flush failed: {}", e);
+                }
+            }
+        });
+    }
+}
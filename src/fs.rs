@@ -6,15 +6,15 @@
 // copied, modified, or distributed except according to those terms.
 //
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
-use std::io::{Error, ErrorKind, SeekFrom};
+use std::io::{self, Error, ErrorKind, SeekFrom};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use bytes::{Buf, Bytes};
-use futures::future::{BoxFuture, FutureExt};
+use futures::future::{BoxFuture, FutureExt, Shared};
 use futures::{future, stream};
 use http::StatusCode;
 use webdav_handler::davpath::DavPath;
@@ -22,16 +22,53 @@ use webdav_handler::fs::{
     DavDirEntry, DavFile, DavFileSystem, DavMetaData, DavProp, FsError, FsFuture, FsResult,
     FsStream, OpenOptions, ReadDirMeta,
 };
+use xmltree::Element;
 
-use crate::api::{PeerApi, PeerEntry};
+use crate::api::{DagOptions, PeerApi, PeerEntry, PeerError};
+use crate::buffer::{WriteBuffer, WritePolicy};
 use crate::cache::Cache;
+use crate::dag::{is_within, DagSettings};
+use crate::flush::{FlushPolicy, Flusher};
+use crate::immutable::{self, Immutable};
+use crate::props::{self, prop_key, PropStore};
+use crate::reader::ReadAhead;
+use crate::readonly::ReadOnly;
+use crate::root::ShareRoot;
+use crate::staging::Staging;
+
+/// XML namespace of the IPFS specific live properties.
+const IPFS_NS: &str = "https://ipfs.tech/ns";
+
+/// Read-only live property holding the CID of a resource.
+const CID_PROP: &str = "cid";
+
+/// Property naming the key a resource is published to IPNS with, setting it
+/// publishes the current CID of the resource.
+const IPNS_KEY_PROP: &str = "ipns-key";
 
 #[derive(Debug, Clone)]
 pub(super) struct PeerFs {
     api: Arc<Box<dyn PeerApi>>,
     cache: Cache,
+    props: PropStore,
+    staging: Staging,
+    write_policy: WritePolicy,
+    dag: DagSettings,
+    read_ahead: usize,
+    flush_policy: FlushPolicy,
+    flusher: Flusher,
+    root: ShareRoot,
+    read_only: ReadOnly,
+    immutable: Immutable,
+    pending: PendingWrites,
 }
 
+// Writes of interrupted uploads still on their way to MFS, by path. A dropped
+// handle cannot wait for its write, so the requests that follow wait for the
+// writes at or below the paths they touch.
+#[derive(Clone, Default)]
+struct PendingWrites(Arc<Mutex<HashMap<String, Shared<BoxFuture<'static, ()>>>>>);
+
 #[derive(Debug, Clone)]
 pub(super) enum PeerNode {
     Dir(PeerDirNode),
@@ -42,15 +79,17 @@ pub(super) enum PeerNode {
 pub(super) struct PeerDirNode {
     mtime: SystemTime,
     crtime: SystemTime,
-    props: HashMap<String, DavProp>,
+    props: Option<HashMap<String, DavProp>>,
+    cid: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub(super) struct PeerFileNode {
     mtime: SystemTime,
     crtime: SystemTime,
-    props: HashMap<String, DavProp>,
+    props: Option<HashMap<String, DavProp>>,
     size: usize,
+    cid: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -60,6 +99,7 @@ struct PeerFsEntry {
     is_dir: bool,
     name: Vec<u8>,
     size: usize,
+    cid: Option<String>,
 }
 
 #[derive(Debug)]
@@ -67,24 +107,98 @@ struct PeerFsFile {
     api: Arc<Box<dyn PeerApi>>,
     cache: Cache,
     path: String,
-    mtime: SystemTime,
-    crtime: SystemTime,
+    staged: Option<String>,
+    file: PeerFileNode,
     pos: usize,
-    size: usize,
+    writable: bool,
     append: bool,
     truncate: bool,
+    immutable: bool,
+    add: bool,
+    dag: DagOptions,
+    buffer: WriteBuffer,
+    reader: Option<ReadAhead>,
+    read_ahead: usize,
+    flush_policy: FlushPolicy,
+    flusher: Flusher,
+    pending: PendingWrites,
 }
 
 impl PeerFs {
-    pub(super) fn new(api: Box<dyn PeerApi>) -> Box<PeerFs> {
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn new(
+        api: Arc<Box<dyn PeerApi>>,
+        cache: Cache,
+        mut root: ShareRoot,
+        read_only: ReadOnly,
+        immutable: Immutable,
+        write_policy: WritePolicy,
+        dag: DagSettings,
+        read_ahead: usize,
+        flush_policy: FlushPolicy,
+        flusher: Flusher,
+    ) -> Box<PeerFs> {
+        // a read-only share leaves MFS as it is
+        let staging = Staging::new(api.clone());
+        if !read_only.is_all() {
+            if write_policy.atomic {
+                staging.start();
+            }
+            root.start(api.clone());
+        }
         Box::new(PeerFs {
-            api: Arc::new(api),
-            cache: Cache::default(),
+            api: api.clone(),
+            cache,
+            props: PropStore::new(api.clone(), flusher.clone(), flush_policy),
+            staging,
+            write_policy,
+            dag,
+            read_ahead,
+            flush_policy,
+            flusher,
+            root,
+            read_only,
+            immutable,
+            pending: PendingWrites::default(),
         })
     }
 
-    fn do_open(&self, path: &str, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
-        let node = match self.cache.get(path) {
+    /// Copies the properties of a collection a COPY request copied, which
+    /// webdav-handler copies by creating collections rather than through `copy`.
+    ///
+    /// Without `deep`, only the properties of the collection itself are copied.
+    pub(super) async fn copy_collection_props(
+        &self,
+        from: &DavPath,
+        to: &DavPath,
+        deep: bool,
+    ) -> FsResult<()> {
+        if self.immutable.path(from).is_some() {
+            return Ok(());
+        }
+        let from = self.root.mfs_path(from)?;
+        let to = self.mutable_path(to)?;
+        if !self.node(&from).await?.is_dir() {
+            return Ok(());
+        }
+        match deep {
+            true => self.props.cp(&from, &to).await?,
+            false => self.props.cp_own(&from, &to).await?,
+        }
+        // the copied nodes were cached before their properties
+        self.cache.remove(&to);
+        self.cache.invalidate_cids(props::META_DIR);
+        Ok(())
+    }
+
+    async fn do_open(&self, path: &str, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        if options.write {
+            self.read_only.check(path)?;
+        }
+        self.pending.wait(path).await;
+        // the handler describes the file by what the handle knows, so take the size
+        // from MFS rather than from a cached entry
+        let node = match self.refresh(path).await {
             Ok(node) => {
                 if options.create_new {
                     return Err(FsError::Exists);
@@ -102,31 +216,221 @@ impl PeerFs {
             Err(e) => return Err(e),
         };
 
-        let size = match node {
-            None => 0,
-            Some(node) => node.as_file().unwrap().size,
+        if options.write {
+            self.root.prepare(self.api.as_ref().as_ref()).await?;
+            self.dag.apply(self.api.as_ref().as_ref()).await?;
+        }
+
+        // only a file written from scratch can be staged, the others are patched in place
+        let from_scratch = options.write && (options.truncate || node.is_none());
+        let staged = match from_scratch && self.write_policy.atomic && !options.append {
+            true => Some(self.staging.path().await?),
+            false => None,
         };
 
-        Ok(Box::new(PeerFsFile {
+        let file = match node {
+            Some(PeerNode::File(file)) if !options.truncate => file,
+            Some(PeerNode::File(file)) => PeerFileNode {
+                size: 0,
+                cid: None,
+                ..file
+            },
+            _ => {
+                let now = SystemTime::now();
+                PeerFileNode {
+                    mtime: now,
+                    crtime: now,
+                    props: None,
+                    size: 0,
+                    cid: None,
+                }
+            }
+        };
+
+        let mut handle = self.handle(path, file);
+        handle.staged = staged;
+        handle.writable = options.write;
+        handle.append = options.append;
+        handle.truncate = from_scratch;
+        Ok(Box::new(handle))
+    }
+
+    // A handle that reads the file, the caller enables writing.
+    fn handle(&self, path: &str, file: PeerFileNode) -> PeerFsFile {
+        PeerFsFile {
             api: self.api.clone(),
             cache: self.cache.clone(),
             path: path.to_string(),
-            crtime: SystemTime::now(),
-            mtime: SystemTime::now(),
+            staged: None,
+            file,
             pos: 0,
-            size,
-            append: options.append,
-            truncate: options.truncate,
-        }))
+            writable: false,
+            append: false,
+            truncate: false,
+            immutable: false,
+            add: self.write_policy.add,
+            dag: self.dag.for_path(path),
+            buffer: WriteBuffer::new(self.write_policy.clone()),
+            reader: None,
+            read_ahead: self.read_ahead,
+            flush_policy: self.flush_policy,
+            flusher: self.flusher.clone(),
+            pending: self.pending.clone(),
+        }
+    }
+
+    // Content outside MFS is looked up once per CID, IPNS names are resolved
+    // again as they move on.
+    async fn immutable_entry(&self, path: &str) -> FsResult<PeerEntry> {
+        if immutable::is_namespace(path) {
+            return Ok(immutable::namespace_entry(path));
+        }
+        let api = self.api.as_ref().as_ref();
+        let path = self.immutable.resolve(api, path).await?;
+        if let Some(entry) = self.immutable.entry(&path) {
+            return Ok(entry);
+        }
+        let entry = api.ipfs_stat(&path).await?;
+        self.immutable
+            .remember_entries(std::slice::from_ref(&entry));
+        Ok(entry)
+    }
+
+    async fn open_immutable(&self, path: &str, options: OpenOptions) -> FsResult<Box<dyn DavFile>> {
+        if options.write {
+            return Err(FsError::Forbidden);
+        }
+        let entry = self.immutable_entry(path).await?;
+        match PeerNode::from_api_entry(&entry) {
+            PeerNode::File(file) => {
+                let mut handle = self.handle(&entry.path, file);
+                handle.immutable = true;
+                Ok(Box::new(handle))
+            }
+            PeerNode::Dir(_) => Err(FsError::Forbidden),
+        }
+    }
+
+    // Publishes the current CID of an entry, flushed so the node can serve it.
+    async fn publish(&self, path: &str, key: &str) -> FsResult<()> {
+        self.api.flush(path).await?;
+        let cid = self.cid(path).await?.ok_or(FsError::NotImplemented)?;
+        let name = self
+            .api
+            .name_publish(&format!("/ipfs/{}", cid), key)
+            .await?;
+        info!("Published {} as /ipns/{} pointing to {}", path, name, cid);
+        Ok(())
+    }
+
+    // Stores the properties of a path and caches them with its node.
+    async fn save_props(&self, path: &str, props: HashMap<String, DavProp>) -> FsResult<()> {
+        self.props.save(path, &props).await?;
+        self.cache.invalidate_cids(props::META_DIR);
+        let mut node = self.node(path).await?;
+        node.set_props(props);
+        self.cache.insert(path, node);
+        Ok(())
+    }
+
+    // Paths of requests that change something, which content outside MFS refuses.
+    fn mutable_path(&self, path: &DavPath) -> FsResult<String> {
+        match self.immutable.path(path) {
+            Some(_) => Err(FsError::Forbidden),
+            None => self.root.mfs_path(path),
+        }
+    }
+
+    async fn read_immutable_dir(&self, path: &str) -> FsResult<Vec<Box<dyn DavDirEntry>>> {
+        if immutable::is_namespace(path) {
+            return Ok(Vec::new());
+        }
+        let api = self.api.as_ref().as_ref();
+        let path = self.immutable.resolve(api, path).await?;
+        let entries = api.ipfs_ls(&path).await?;
+        self.immutable.remember_entries(&entries);
+        Ok(entries
+            .iter()
+            .map(|e| Box::new(PeerNode::from_api_entry(e).to_entry(&e.path)) as _)
+            .collect())
+    }
+
+    async fn revalidate(&self) -> FsResult<()> {
+        self.root.ready(self.api.as_ref().as_ref()).await?;
+        if self.cache.revalidation_due() {
+            let root = self.api.stat("/").await?;
+            self.cache.revalidate(root.cid.as_deref());
+        }
+        Ok(())
+    }
+
+    // Fetches the metadata of a path again, dropping the cached entry if it is gone.
+    async fn refresh(&self, path: &str) -> FsResult<PeerNode> {
+        match self.api.stat(path).await {
+            Ok(entry) => {
+                let node = PeerNode::from_api_entry(&entry);
+                self.cache.update(path, node.clone());
+                Ok(node)
+            }
+            Err(PeerError::NotFound) => {
+                self.cache.remove(path);
+                Err(FsError::NotFound)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Nodes may have been evicted since the request looked them up.
+    async fn node(&self, path: &str) -> FsResult<PeerNode> {
+        match self.cache.get(path) {
+            Err(FsError::NotFound) => {
+                let node = PeerNode::from_api_entry(&self.api.stat(path).await?);
+                self.cache.insert(path, node.clone());
+                Ok(node)
+            }
+            res => res,
+        }
+    }
+
+    // CIDs of ancestors are dropped on every change below them, so look them up again.
+    async fn cid(&self, path: &str) -> FsResult<Option<String>> {
+        let mut node = self.node(path).await?;
+        if let Some(cid) = node.cid() {
+            return Ok(Some(cid.to_string()));
+        }
+        let entry = self.api.stat(path).await?;
+        node.set_cid(entry.cid.clone());
+        self.cache.insert(path, node);
+        Ok(entry.cid)
+    }
+
+    // Dead properties are read from MFS the first time a node is asked for them.
+    async fn props(&self, path: &str) -> FsResult<HashMap<String, DavProp>> {
+        let mut node = self.node(path).await?;
+        if let Some(props) = node.props() {
+            return Ok(props.clone());
+        }
+        let props = self.props.load(path).await?;
+        node.set_props(props.clone());
+        self.cache.insert(path, node);
+        Ok(props)
     }
 }
 
 impl DavFileSystem for PeerFs {
-    fn open<'a>(&'a self, path: &'a DavPath, options: OpenOptions) -> FsFuture<Box<dyn DavFile>> {
+    fn open<'a>(
+        &'a self,
+        path: &'a DavPath,
+        options: OpenOptions,
+    ) -> FsFuture<'a, Box<dyn DavFile>> {
         async move {
             trace!("DFS: open {:?}", path);
-            let path = path_to_string(path);
-            self.do_open(&path, options)
+            if let Some(path) = self.immutable.path(path) {
+                return self.open_immutable(&path?, options).await;
+            }
+            let path = self.root.mfs_path(path)?;
+            check_visible(&path)?;
+            self.do_open(&path, options).await
         }
         .boxed()
     }
@@ -135,103 +439,195 @@ impl DavFileSystem for PeerFs {
         &'a self,
         path: &'a DavPath,
         _meta: ReadDirMeta,
-    ) -> FsFuture<FsStream<Box<dyn DavDirEntry>>> {
+    ) -> FsFuture<'a, FsStream<Box<dyn DavDirEntry>>> {
         async move {
             trace!("DFS: read_dir {:?}", path);
-            let path = path_to_string(path);
+            if let Some(path) = self.immutable.path(path) {
+                let v = self.read_immutable_dir(&path?).await?;
+                return Ok(Box::pin(stream::iter(v)) as FsStream<Box<dyn DavDirEntry>>);
+            }
+            let path = self.root.mfs_path(path)?;
+            check_visible(&path)?;
+            self.pending.wait(&path).await;
+            self.revalidate().await?;
             let mut v: Vec<Box<dyn DavDirEntry>> = Vec::new();
-            if let Ok(entries) = self.api.ls(&path).await {
-                for entry in entries {
-                    let node = PeerNode::from_api_entry(&entry);
-                    v.push(Box::new(node.to_entry(&entry.path)));
-                    self.cache.insert(&entry.path, node);
+            let mut names = HashSet::new();
+            // the namespaces shadow MFS entries of the same name
+            let mounts = match path == self.root.path() {
+                true => self.immutable.mounts(),
+                false => &[],
+            };
+            for mount in mounts {
+                let entry = immutable::namespace_entry(&format!("/{}", mount));
+                v.push(Box::new(
+                    PeerNode::from_api_entry(&entry).to_entry(&entry.path),
+                ));
+            }
+            let entries = self.api.ls(&path).await?;
+            // a listing is usually followed by the properties of every entry, so
+            // the ones not cached yet are loaded together
+            let mut stored = match entries.iter().all(|e| self.cache.has_props(&e.path)) {
+                true => HashMap::new(),
+                false => self.props.load_children(&path).await?,
+            };
+            for entry in entries.into_iter().filter(|e| !props::is_hidden(&e.path)) {
+                let mut node = PeerNode::from_api_entry(&entry);
+                let dir_entry = node.to_entry(&entry.path);
+                let name = String::from_utf8_lossy(&dir_entry.name).into_owned();
+                if mounts.contains(&name) {
+                    warn!("{} is hidden by the mount of the same name", entry.path);
+                    continue;
                 }
+                if !self.cache.has_props(&entry.path) {
+                    node.set_props(stored.remove(&name).unwrap_or_default());
+                }
+                names.insert(name);
+                v.push(Box::new(dir_entry));
+                self.cache.update(&entry.path, node);
             }
+            self.cache.retain_children(&path, &names);
             let stream = stream::iter(v);
             Ok(Box::pin(stream) as FsStream<Box<dyn DavDirEntry>>)
         }
         .boxed()
     }
 
-    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<Box<dyn DavMetaData>> {
+    fn metadata<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, Box<dyn DavMetaData>> {
         async move {
-            let path = path_to_string(path);
-            if !self.cache.contains(&path) {
-                if let Ok(entry) = self.api.stat(&path).await {
-                    self.cache.insert(&path, PeerNode::from_api_entry(&entry));
-                }
+            if let Some(path) = self.immutable.path(path) {
+                let path = path?;
+                let entry = self.immutable_entry(&path).await?;
+                let entry = PeerNode::from_api_entry(&entry).to_entry(&path);
+                return Ok(Box::new(entry) as Box<dyn DavMetaData>);
+            }
+            let path = self.root.mfs_path(path)?;
+            check_visible(&path)?;
+            self.pending.wait(&path).await;
+            self.revalidate().await?;
+            if !self.cache.is_fresh(&path) {
+                self.refresh(&path).await?;
             }
+            self.cid(&path).await?;
             let entry = self.cache.get(&path)?.to_entry(&path);
             Ok(Box::new(entry) as Box<dyn DavMetaData>)
         }
         .boxed()
     }
 
-    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
+    fn create_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
         async move {
             trace!("DFS: create_dir {:?}", path);
-            let path = path_to_string(path);
+            let path = self.mutable_path(path)?;
+            check_visible(&path)?;
+            self.read_only.check(&path)?;
             if self.cache.get(&path).is_ok() {
                 return Err(FsError::Exists);
             }
             let parent = parent_path(&path);
-            if parent != "/" && !self.cache.get(&parent)?.is_dir() {
+            if parent != self.root.path() && !self.node(&parent).await?.is_dir() {
                 return Err(FsError::Forbidden);
             }
-            if let Ok(entry) = self.api.mkdir(&path).await {
-                self.cache.insert(&path, PeerNode::from_api_entry(&entry));
-            }
-            Ok(())
+            self.root.prepare(self.api.as_ref().as_ref()).await?;
+            self.dag.apply(self.api.as_ref().as_ref()).await?;
+            let entry = self
+                .api
+                .mkdir_with(&path, &self.dag.for_path(&path))
+                .await?;
+            self.cache.insert(&path, PeerNode::from_api_entry(&entry));
+            self.cache.invalidate_cids(&path);
+            Ok(self.flusher.closed(&path, self.flush_policy).await?)
         }
         .boxed()
     }
 
-    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
+    fn remove_dir<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
         async move {
             trace!("DFS: remove_dir {:?}", path);
-            let path = path_to_string(path);
-            if self.api.rm(&path).await.is_ok() {
-                self.cache.remove(&path);
+            let path = self.mutable_path(path)?;
+            check_visible(&path)?;
+            self.read_only.check(&path)?;
+            if path == self.root.path() {
+                return Err(FsError::Forbidden);
             }
-            Ok(())
+            self.pending.wait(&path).await;
+            self.api.rm(&path).await?;
+            self.cache.remove(&path);
+            self.cache.invalidate_cids(&path);
+            self.props.rm(&path).await?;
+            Ok(self
+                .flusher
+                .closed(&parent_path(&path), self.flush_policy)
+                .await?)
         }
         .boxed()
     }
 
-    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<()> {
+    fn remove_file<'a>(&'a self, path: &'a DavPath) -> FsFuture<'a, ()> {
         async move {
             trace!("DFS: remove_file {:?}", path);
-            let path = path_to_string(path);
-            if self.api.rm(&path).await.is_ok() {
-                self.cache.remove(&path);
-            }
-            Ok(())
+            let path = self.mutable_path(path)?;
+            check_visible(&path)?;
+            self.read_only.check(&path)?;
+            self.pending.wait(&path).await;
+            self.api.rm(&path).await?;
+            self.cache.remove(&path);
+            self.cache.invalidate_cids(&path);
+            self.props.rm(&path).await?;
+            Ok(self
+                .flusher
+                .closed(&parent_path(&path), self.flush_policy)
+                .await?)
         }
         .boxed()
     }
 
-    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<()> {
+    fn rename<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
         async move {
             trace!("DFS: rename {:?} {:?}", from, to);
-            let from = path_to_string(from);
-            let to = path_to_string(to);
-            if self.api.mv(&from, &to).await.is_ok() {
-                self.cache.mv_vals(&from, &to);
+            let from = self.mutable_path(from)?;
+            let to = self.mutable_path(to)?;
+            check_visible(&from)?;
+            check_visible(&to)?;
+            self.read_only.check(&from)?;
+            self.read_only.check(&to)?;
+            if from == self.root.path() {
+                return Err(FsError::Forbidden);
             }
-            Ok(())
+            self.pending.wait(&from).await;
+            self.pending.wait(&to).await;
+            self.api.mv(&from, &to).await?;
+            self.cache.mv_vals(&from, &to);
+            self.cache.invalidate_cids(&from);
+            self.cache.invalidate_cids(&to);
+            self.props.mv(&from, &to).await?;
+            Ok(self.flusher.closed(&to, self.flush_policy).await?)
         }
         .boxed()
     }
 
-    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<()> {
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
         async move {
             trace!("DFS: copy {:?} {:?}", from, to);
-            let from = path_to_string(from);
-            let to = path_to_string(to);
-            if self.api.cp(&from, &to).await.is_ok() {
-                self.cache.cp_vals(&from, &to);
+            let to = self.mutable_path(to)?;
+            check_visible(&to)?;
+            self.read_only.check(&to)?;
+            self.pending.wait(&to).await;
+            // links the content into MFS without fetching it
+            if let Some(from) = self.immutable.path(from) {
+                let api = self.api.as_ref().as_ref();
+                let from = self.immutable.resolve(api, &from?).await?;
+                self.api.cp(&from, &to).await?;
+                self.cache.invalidate_cids(&to);
+                return Ok(self.flusher.closed(&to, self.flush_policy).await?);
             }
-            Ok(())
+            let from = self.root.mfs_path(from)?;
+            check_visible(&from)?;
+            self.pending.wait(&from).await;
+            self.api.cp(&from, &to).await?;
+            self.cache.cp_vals(&from, &to);
+            self.cache.invalidate_cids(&to);
+            self.props.cp(&from, &to).await?;
+            Ok(self.flusher.closed(&to, self.flush_policy).await?)
         }
         .boxed()
     }
@@ -244,20 +640,38 @@ impl DavFileSystem for PeerFs {
         &'a self,
         path: &'a DavPath,
         patch: Vec<(bool, DavProp)>,
-    ) -> FsFuture<Vec<(StatusCode, DavProp)>> {
+    ) -> FsFuture<'a, Vec<(StatusCode, DavProp)>> {
         async move {
-            let path = path_to_string(path);
-            let node = &mut self.cache.get(&path)?;
-            let props = node.props_mut();
+            let path = self.mutable_path(path)?;
+            self.read_only.check(&path)?;
+            let mut props = self.props(&path).await?;
+
+            // a protected property fails the whole update (RFC4918 9.2)
+            if patch.iter().any(|(_, p)| is_cid_prop(p)) {
+                return Ok(fail_patch(&patch, is_cid_prop, StatusCode::FORBIDDEN));
+            }
+
+            let key = patch
+                .iter()
+                .rev()
+                .find(|(set, p)| *set && is_ipns_key_prop(p))
+                .map(|(_, p)| prop_text(p));
+            if let Some(key) = &key {
+                let keys = self.api.key_list().await?;
+                if !keys.iter().any(|k| &k.name == key) {
+                    return Ok(fail_patch(&patch, is_ipns_key_prop, StatusCode::CONFLICT));
+                }
+            }
 
+            let before = props.clone();
             let mut res = Vec::new();
             for (set, p) in patch.into_iter() {
                 let prop = clone_prop(&p);
                 let status = if set {
-                    props.insert(prop_key(&p.namespace, &p.name), p);
+                    props.insert(prop_key(&p), p);
                     StatusCode::OK
                 } else {
-                    props.remove(&prop_key(&p.namespace, &p.name));
+                    props.remove(&prop_key(&p));
                     // the below map was added to signify if the remove succeeded or
                     // failed. however it seems that removing non-existent properties
                     // always succeed, so just return success.
@@ -266,35 +680,59 @@ impl DavFileSystem for PeerFs {
                 };
                 res.push((status, prop));
             }
-            self.cache.insert(&path, node.to_owned());
+            self.save_props(&path, props).await?;
+
+            // the key is only published once it is stored, and dropped again if that fails
+            if let Some(key) = key {
+                if let Err(e) = self.publish(&path, &key).await {
+                    self.save_props(&path, before).await?;
+                    return Err(e);
+                }
+            }
             Ok(res)
         }
         .boxed()
     }
 
-    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<Vec<DavProp>> {
+    fn get_props<'a>(&'a self, path: &'a DavPath, do_content: bool) -> FsFuture<'a, Vec<DavProp>> {
         async move {
-            let path = path_to_string(path);
-            Ok(self
-                .cache
-                .get(&path)?
-                .props()
+            let (props, cid) = match self.immutable.path(path) {
+                Some(path) => (HashMap::new(), self.immutable_entry(&path?).await?.cid),
+                None => {
+                    let path = self.root.mfs_path(path)?;
+                    (self.props(&path).await?, self.cid(&path).await?)
+                }
+            };
+            let mut props: Vec<DavProp> = props
                 .values()
                 .map(|p| if do_content { p.clone() } else { clone_prop(p) })
-                .collect())
+                .collect();
+            if let Some(cid) = cid {
+                let prop = cid_prop(&cid);
+                props.push(if do_content { prop } else { clone_prop(&prop) });
+            }
+            Ok(props)
         }
         .boxed()
     }
 
-    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<Vec<u8>> {
+    fn get_prop<'a>(&'a self, path: &'a DavPath, prop: DavProp) -> FsFuture<'a, Vec<u8>> {
         async move {
-            let path = path_to_string(path);
-            let node = &self.cache.get(&path)?;
-            let p = node
-                .props()
-                .get(&prop_key(&prop.namespace, &prop.name))
-                .ok_or(FsError::NotFound)?;
-            p.xml.clone().ok_or(FsError::NotFound)
+            if let Some(path) = self.immutable.path(path) {
+                let cid = self.immutable_entry(&path?).await?.cid;
+                return match cid {
+                    Some(cid) if is_cid_prop(&prop) => cid_prop(&cid).xml.ok_or(FsError::NotFound),
+                    _ => Err(FsError::NotFound),
+                };
+            }
+            let path = self.root.mfs_path(path)?;
+            if is_cid_prop(&prop) {
+                let cid = self.cid(&path).await?.ok_or(FsError::NotFound)?;
+                return cid_prop(&cid).xml.ok_or(FsError::NotFound);
+            }
+            let mut props = self.props(&path).await?;
+            let p = props.remove(&prop_key(&prop)).ok_or(FsError::NotFound)?;
+            p.xml.ok_or(FsError::NotFound)
         }
         .boxed()
     }
@@ -306,25 +744,43 @@ impl PeerNode {
             PeerNode::Dir(PeerDirNode {
                 crtime: entry.crtime,
                 mtime: entry.mtime,
-                props: HashMap::new(),
+                props: None,
+                cid: entry.cid.clone(),
             })
         } else {
             PeerNode::File(PeerFileNode {
                 crtime: entry.crtime,
                 mtime: entry.mtime,
-                props: HashMap::new(),
+                props: None,
                 size: entry.size,
+                cid: entry.cid.clone(),
             })
         }
     }
 
-    fn from_fs_file(file: &PeerFsFile) -> Self {
-        PeerNode::File(PeerFileNode {
-            crtime: file.crtime,
-            mtime: file.mtime,
-            props: HashMap::new(),
-            size: file.size,
-        })
+    // Takes over fresh metadata, but keeps the properties unless the node brings its own.
+    pub(super) fn merge(&mut self, fresh: PeerNode) {
+        let props = self.props().cloned();
+        *self = fresh;
+        if let (None, Some(props)) = (self.props(), props) {
+            self.set_props(props);
+        }
+    }
+
+    // Rough amount of heap memory held by the node, for the cache budget.
+    pub(super) fn heap_size(&self) -> usize {
+        let (props, cid) = match &self {
+            PeerNode::Dir(ref d) => (&d.props, &d.cid),
+            PeerNode::File(ref f) => (&f.props, &f.cid),
+        };
+        let props = props.iter().flatten().map(|(k, p)| {
+            k.len()
+                + p.name.len()
+                + p.prefix.as_ref().map_or(0, |s| s.len())
+                + p.namespace.as_ref().map_or(0, |s| s.len())
+                + p.xml.as_ref().map_or(0, |s| s.len())
+        });
+        props.sum::<usize>() + cid.as_ref().map_or(0, |s| s.len())
     }
 
     // Helper to create PeerFsDirEntry from a node
@@ -345,6 +801,21 @@ impl PeerNode {
             is_dir,
             name,
             size,
+            cid: self.cid().map(|c| c.to_string()),
+        }
+    }
+
+    pub(super) fn cid(&self) -> Option<&str> {
+        match &self {
+            PeerNode::Dir(ref d) => d.cid.as_deref(),
+            PeerNode::File(ref f) => f.cid.as_deref(),
+        }
+    }
+
+    pub(super) fn set_cid(&mut self, cid: Option<String>) {
+        match self {
+            PeerNode::Dir(ref mut d) => d.cid = cid,
+            PeerNode::File(ref mut f) => f.cid = cid,
         }
     }
 
@@ -362,27 +833,74 @@ impl PeerNode {
         }
     }
 
-    fn props(&self) -> &HashMap<String, DavProp> {
+    pub(super) fn props(&self) -> Option<&HashMap<String, DavProp>> {
         match &self {
-            PeerNode::Dir(ref d) => &d.props,
-            PeerNode::File(ref f) => &f.props,
+            PeerNode::Dir(ref d) => d.props.as_ref(),
+            PeerNode::File(ref f) => f.props.as_ref(),
         }
     }
 
-    fn props_mut(&mut self) -> &mut HashMap<String, DavProp> {
+    fn set_props(&mut self, props: HashMap<String, DavProp>) {
         match self {
-            PeerNode::Dir(ref mut d) => &mut d.props,
-            PeerNode::File(ref mut f) => &mut f.props,
+            PeerNode::Dir(ref mut d) => d.props = Some(props),
+            PeerNode::File(ref mut f) => f.props = Some(props),
         }
     }
 }
 
+impl PendingWrites {
+    // Runs a write on the runtime, after the one pending for the same path.
+    fn spawn(&self, runtime: &tokio::runtime::Handle, path: &str, write: BoxFuture<'static, ()>) {
+        let mut writes = self.0.lock().unwrap();
+        let before = writes.get(path).cloned();
+        let write = async move {
+            if let Some(before) = before {
+                before.await;
+            }
+            write.await;
+        }
+        .boxed()
+        .shared();
+        writes.insert(path.to_string(), write.clone());
+        let pending = self.clone();
+        let path = path.to_string();
+        runtime.spawn(async move {
+            write.clone().await;
+            let mut writes = pending.0.lock().unwrap();
+            if writes.get(&path).is_some_and(|w| w.ptr_eq(&write)) {
+                writes.remove(&path);
+            }
+        });
+    }
+
+    // Waits for the writes at or below a path.
+    async fn wait(&self, path: &str) {
+        let writes: Vec<_> = self
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(p, _)| is_within(p, path))
+            .map(|(_, w)| w.clone())
+            .collect();
+        future::join_all(writes).await;
+    }
+}
+
+impl Debug for PendingWrites {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingWrites")
+            .field("paths", &self.0.lock().unwrap().keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
 impl DavDirEntry for PeerFsEntry {
     fn name(&self) -> Vec<u8> {
         self.name.clone()
     }
 
-    fn metadata(&self) -> FsFuture<Box<dyn DavMetaData>> {
+    fn metadata(&self) -> FsFuture<'_, Box<dyn DavMetaData>> {
         let meta = (*self).clone();
         Box::pin(future::ok(Box::new(meta) as Box<dyn DavMetaData>))
     }
@@ -404,39 +922,123 @@ impl DavMetaData for PeerFsEntry {
     fn created(&self) -> FsResult<SystemTime> {
         Ok(self.crtime)
     }
+
+    // The CID changes exactly when the content does, which makes it a strong validator.
+    fn etag(&self) -> Option<String> {
+        match &self.cid {
+            Some(cid) => Some(cid.clone()),
+            None => {
+                let t = self.mtime.duration_since(UNIX_EPOCH).ok()?.as_micros();
+                Some(format!("{:x}-{:x}", self.size, t))
+            }
+        }
+    }
 }
 
 impl PeerFsFile {
-    async fn do_write(&mut self, buf: Bytes) {
+    async fn do_write(&mut self, buf: Bytes) -> FsResult<()> {
         if self.append {
-            self.pos = self.size;
+            self.pos = self.file.size;
         }
-        self.size = self.pos + buf.len();
-        let _ = self
-            .api
-            .write(&self.path, self.pos, self.truncate, buf)
-            .await;
-        self.pos = self.size;
-        self.truncate = false;
+        if !self.buffer.is_empty() && self.buffer.end() != self.pos {
+            self.write_back().await?;
+        }
+        self.buffer.push(self.pos, &buf)?;
+        self.pos += buf.len();
+        self.file.size = self.file.size.max(self.pos);
+        self.file.mtime = SystemTime::now();
+        self.file.cid = None;
+        Ok(())
+    }
+
+    // Sends the buffered bytes to MFS.
+    async fn write_back(&mut self) -> FsResult<()> {
+        if let Some((offset, body)) = self.buffer.take()? {
+            // whatever was read ahead may be stale now
+            self.reader = None;
+            let path = self.staged.as_ref().unwrap_or(&self.path);
+            if self.add && offset == 0 && self.truncate {
+                self.api.add(path, body, &self.dag).await?;
+            } else {
+                self.api
+                    .write_from(path, offset, self.truncate, body, &self.dag)
+                    .await?;
+            }
+            self.truncate = false;
+            self.flusher.written(path, self.flush_policy).await?;
+        }
+        Ok(())
+    }
+}
+
+// An upload that broke off is never flushed. What arrived is kept so the client
+// can resume it, unless it was staged, which leaves the destination untouched.
+// The write is registered with the pending writes, which later requests wait for.
+impl Drop for PeerFsFile {
+    fn drop(&mut self) {
+        let staged = self.staged.take();
+        let pending = self.buffer.take().unwrap_or_else(|e| {
+            warn!("Lost buffered upload of {}: {}", self.path, e);
+            None
+        });
+        if staged.is_none() && pending.is_none() {
+            return;
+        }
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => runtime,
+            Err(_) => return,
+        };
+        let api = self.api.clone();
+        let cache = self.cache.clone();
+        let path = self.path.clone();
+        let dag = self.dag.clone();
+        let truncate = self.truncate;
+        let flusher = self.flusher.clone();
+        let policy = self.flush_policy;
+        if let Some(staged) = staged {
+            runtime.spawn(async move {
+                if let Err(e) = api.rm(&staged).await {
+                    warn!("Failed to remove staged upload {}: {}", staged, e);
+                }
+            });
+            return;
+        }
+        let (offset, body) = pending.unwrap();
+        let write = async move {
+            let res = async {
+                api.write_from(&path, offset, truncate, body, &dag).await?;
+                flusher.closed(&path, policy).await?;
+                api.stat(&path).await
+            };
+            match res.await {
+                Ok(entry) => {
+                    cache.update(&path, PeerNode::from_api_entry(&entry));
+                    cache.invalidate_cids(&path);
+                }
+                Err(e) => warn!("Failed to keep interrupted upload of {}: {}", path, e),
+            }
+        };
+        self.pending.spawn(&runtime, &self.path, write.boxed());
     }
 }
 
 impl DavFile for PeerFsFile {
-    fn metadata(&mut self) -> FsFuture<Box<dyn DavMetaData>> {
+    // Reflects what was written so far, even before it reached MFS.
+    fn metadata(&mut self) -> FsFuture<'_, Box<dyn DavMetaData>> {
         async move {
-            let entry = self.cache.get(&self.path)?.to_entry(&self.path);
+            let entry = PeerNode::File(self.file.clone()).to_entry(&self.path);
             Ok(Box::new(entry) as Box<dyn DavMetaData>)
         }
         .boxed()
     }
 
-    fn write_buf(&mut self, mut buf: Box<dyn Buf + Send>) -> FsFuture<()> {
+    fn write_buf(&mut self, mut buf: Box<dyn Buf + Send>) -> FsFuture<'_, ()> {
         async move {
             trace!("DF: write_buf");
             while buf.has_remaining() {
                 let b = buf.chunk();
                 let len = b.len();
-                self.do_write(Bytes::copy_from_slice(b)).await;
+                self.do_write(Bytes::copy_from_slice(b)).await?;
                 buf.advance(len);
             }
             Ok(())
@@ -444,69 +1046,101 @@ impl DavFile for PeerFsFile {
         .boxed()
     }
 
-    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<()> {
+    fn write_bytes(&mut self, buf: Bytes) -> FsFuture<'_, ()> {
         async move {
             trace!("DF: write_bytes");
-            self.do_write(buf).await;
-            Ok(())
+            self.do_write(buf).await
         }
         .boxed()
     }
 
-    fn read_bytes(&mut self, count: usize) -> FsFuture<Bytes> {
+    fn read_bytes(&mut self, count: usize) -> FsFuture<'_, Bytes> {
         async move {
             trace!("DF: read_bytes ({:?} bytes)", count);
-            let res = self.api.read(&self.path, self.pos, count).await;
-            self.pos += count;
-            match res {
-                Ok(bytes) => Ok(bytes),
-                Err(_) => Err(FsError::GeneralFailure),
+            self.write_back().await?;
+            let count = count.min(self.file.size.saturating_sub(self.pos));
+            if count == 0 {
+                return Ok(Bytes::new());
             }
+            // keep streaming as long as the client reads sequentially
+            let reader = match self.reader.take() {
+                Some(reader) if reader.pos() == self.pos => reader,
+                _ => {
+                    let path = self.staged.as_ref().unwrap_or(&self.path);
+                    let stream = match self.immutable {
+                        true => self.api.cat(path, self.pos).await?,
+                        false => self.api.read_stream(path, self.pos).await?,
+                    };
+                    ReadAhead::new(stream, self.pos, self.read_ahead)
+                }
+            };
+            let data = self.reader.insert(reader).read(count).await?;
+            self.pos += data.len();
+            Ok(data)
         }
         .boxed()
     }
 
-    fn seek(&mut self, pos: SeekFrom) -> FsFuture<u64> {
+    fn seek(&mut self, pos: SeekFrom) -> FsFuture<'_, u64> {
         async move {
             trace!("DF: seek");
             let (start, offset): (u64, i64) = match pos {
-                SeekFrom::Start(pos) => {
-                    self.pos = pos as usize;
-                    return Ok(pos);
-                }
+                SeekFrom::Start(pos) => (pos, 0),
                 SeekFrom::Current(pos) => (self.pos as u64, pos),
-                SeekFrom::End(pos) => (self.size as u64, pos),
+                SeekFrom::End(pos) => (self.file.size as u64, pos),
             };
-            if offset < 0 {
-                if -offset as u64 > start {
-                    return Err(Error::new(ErrorKind::InvalidInput, "invalid seek").into());
+            let pos = match offset < 0 {
+                true => start.checked_sub(-offset as u64),
+                false => Some(start + offset as u64),
+            };
+            // a partial upload must not leave a gap, which MFS would fill with zeros
+            let end = self.file.size.max(self.buffer.end()) as u64;
+            match pos {
+                Some(pos) if !self.writable || pos <= end => {
+                    self.pos = pos as usize;
+                    Ok(pos)
                 }
-                self.pos = (start - (-offset as u64)) as usize;
-            } else {
-                self.pos = (start + offset as u64) as usize;
+                _ => Err(Error::new(ErrorKind::InvalidInput, "invalid seek").into()),
             }
-            Ok(self.pos as u64)
         }
         .boxed()
     }
 
-    fn flush(&mut self) -> FsFuture<()> {
+    fn flush(&mut self) -> FsFuture<'_, ()> {
         async move {
             trace!("DF: flush");
-            if self.api.flush(&self.path).await.is_ok() {
-                self.cache.insert(&self.path, PeerNode::from_fs_file(self));
+            self.write_back().await?;
+            let path = self.staged.as_ref().unwrap_or(&self.path);
+            if self.truncate {
+                // nothing was written, but the file still has to be created or emptied
+                let body = Box::new(io::empty());
+                self.api.write_from(path, 0, true, body, &self.dag).await?;
+                self.truncate = false;
+            }
+            if let Some(staged) = self.staged.take() {
+                if let Err(e) = self.api.mv(&staged, &self.path).await {
+                    let _ = self.api.rm(&staged).await;
+                    return Err(e.into());
+                }
             }
+            // an added file keeps the CID `ipfs add` gives it, the time store has its mtime
+            if !self.add {
+                self.api.touch(&self.path, self.file.mtime).await?;
+            }
+            self.flusher.closed(&self.path, self.flush_policy).await?;
+            let entry = self.api.stat(&self.path).await?;
+            let node = PeerNode::from_api_entry(&entry);
+            if let Ok(file) = node.as_file() {
+                self.file = file.clone();
+            }
+            self.cache.update(&self.path, node);
+            self.cache.invalidate_cids(&self.path);
             Ok(())
         }
         .boxed()
     }
 }
 
-#[inline]
-fn path_to_string(path: &DavPath) -> String {
-    pb_to_string(path.as_pathbuf())
-}
-
 #[inline]
 fn parent_path(path: &str) -> String {
     pb_to_string(Path::new(path).parent().unwrap().to_path_buf())
@@ -517,9 +1151,61 @@ fn pb_to_string(path: PathBuf) -> String {
     path.into_os_string().into_string().unwrap()
 }
 
+// The sidecar data must not be reachable, nor clobbered, through WebDAV.
+#[inline]
+fn check_visible(path: &str) -> FsResult<()> {
+    match props::is_hidden(path) {
+        true => Err(FsError::NotFound),
+        false => Ok(()),
+    }
+}
+
+#[inline]
+fn is_cid_prop(p: &DavProp) -> bool {
+    p.name == CID_PROP && p.namespace.as_deref() == Some(IPFS_NS)
+}
+
 #[inline]
-fn prop_key(ns: &Option<String>, name: &str) -> String {
-    ns.to_owned().as_ref().unwrap_or(&"".to_string()).clone() + name
+fn is_ipns_key_prop(p: &DavProp) -> bool {
+    p.name == IPNS_KEY_PROP && p.namespace.as_deref() == Some(IPFS_NS)
+}
+
+// The text content of a property set by PROPPATCH, which comes as a whole element.
+fn prop_text(p: &DavProp) -> String {
+    p.xml
+        .as_deref()
+        .and_then(|xml| Element::parse(xml).ok())
+        .and_then(|e| e.get_text().map(|t| t.trim().to_string()))
+        .unwrap_or_default()
+}
+
+// Fails a whole property update because of the properties matching `failed`.
+fn fail_patch(
+    patch: &[(bool, DavProp)],
+    failed: fn(&DavProp) -> bool,
+    status: StatusCode,
+) -> Vec<(StatusCode, DavProp)> {
+    patch
+        .iter()
+        .map(|(_, p)| match failed(p) {
+            true => (status, clone_prop(p)),
+            false => (StatusCode::FAILED_DEPENDENCY, clone_prop(p)),
+        })
+        .collect()
+}
+
+#[inline]
+fn cid_prop(cid: &str) -> DavProp {
+    let xml = format!(
+        r#"<I:{0} xmlns:I="{1}">{2}</I:{0}>"#,
+        CID_PROP, IPFS_NS, cid
+    );
+    DavProp {
+        name: CID_PROP.to_string(),
+        namespace: Some(IPFS_NS.to_string()),
+        prefix: Some("I".to_string()),
+        xml: Some(xml.into_bytes()),
+    }
 }
 
 #[inline]
@@ -0,0 +1,171 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::collections::HashMap;
+use std::path::Component;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use webdav_handler::davpath::DavPath;
+use webdav_handler::fs::FsResult;
+
+use crate::api::{PeerApi, PeerEntry, PeerError};
+use crate::error::BuildError;
+use crate::root::join_dav_path;
+
+/// Names of the namespaces shown next to the MFS tree.
+pub(super) const NAMESPACES: [&str; 2] = ["ipfs", "ipns"];
+
+/// How long an IPNS name is trusted to point where it was resolved to.
+const NAME_TTL: Duration = Duration::from_secs(60);
+
+/// Number of resolved IPNS names kept at most.
+const MAX_NAMES: usize = 1024;
+
+/// Number of looked up `/ipfs/` entries kept at most.
+const MAX_ENTRIES: usize = 4096;
+
+/// Read-only view of content outside MFS, addressed by `/ipfs/<cid>` and
+/// `/ipns/<name>` paths at the root of the share.
+///
+/// The namespaces are mounted at the root of the share, under their own names
+/// unless others are configured, and hide MFS entries of the same name. Neither
+/// can be listed, but everything below a CID or a name can. Names are resolved
+/// to CIDs first, since resolving them again for every request of a PROPFIND
+/// would take seconds each. What a CID points to never changes, so the entries
+/// below it are kept once looked up or listed.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Immutable {
+    enabled: bool,
+    mounts: Vec<String>,
+    names: Arc<Mutex<HashMap<String, (String, Instant)>>>,
+    entries: Arc<Mutex<HashMap<String, (PeerEntry, Instant)>>>,
+}
+
+impl Immutable {
+    /// Creates the view with the names the `ipfs` and `ipns` namespaces are mounted at.
+    pub(super) fn new(enabled: bool, mounts: [String; 2]) -> Result<Self, BuildError> {
+        for mount in &mounts {
+            if mount.is_empty() || mount.contains('/') || mount == "." || mount == ".." {
+                return Err(BuildError::InvalidMount(mount.clone()));
+            }
+        }
+        Ok(Immutable {
+            enabled,
+            mounts: mounts.into(),
+            ..Default::default()
+        })
+    }
+
+    /// Names of the entries the namespaces take at the root of the share.
+    pub(super) fn mounts(&self) -> &[String] {
+        match self.enabled {
+            true => &self.mounts,
+            false => &[],
+        }
+    }
+
+    /// The `/ipfs/` or `/ipns/` path a WebDAV path stands for, `None` for MFS paths.
+    pub(super) fn path(&self, path: &DavPath) -> Option<FsResult<String>> {
+        let mount = match path.as_rel_ospath().components().next() {
+            Some(Component::Normal(name)) => self.mounts().iter().position(|m| name == m.as_str()),
+            _ => None,
+        }?;
+        let path = join_dav_path(String::new(), path).map(|p| {
+            let rest = &p[1 + self.mounts[mount].len()..];
+            format!("/{}{}", NAMESPACES[mount], rest)
+        });
+        Some(path)
+    }
+
+    /// Resolves the IPNS name a path starts with, giving an `/ipfs/` path.
+    pub(super) async fn resolve(&self, api: &dyn PeerApi, path: &str) -> Result<String, PeerError> {
+        let rest = match path.strip_prefix("/ipns/") {
+            Some(rest) => rest,
+            None => return Ok(path.to_string()),
+        };
+        let (name, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let cached = match self.names.lock().unwrap().get(name) {
+            Some((target, at)) if at.elapsed() < NAME_TTL => Some(target.clone()),
+            _ => None,
+        };
+        let target = match cached {
+            Some(target) => target,
+            None => {
+                let target = api.resolve_name(name).await?;
+                self.remember(name, &target);
+                target
+            }
+        };
+        Ok(format!("{}{}", target.trim_end_matches('/'), rest))
+    }
+
+    /// The entry of an `/ipfs/` path that was looked up or listed before.
+    pub(super) fn entry(&self, path: &str) -> Option<PeerEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(path).map(|(entry, _)| entry.clone())
+    }
+
+    /// Keeps entries of `/ipfs/` paths, e.g. the ones a listing gave.
+    pub(super) fn remember_entries(&self, entries: &[PeerEntry]) {
+        let cached = &mut *self.entries.lock().unwrap();
+        for entry in entries.iter().filter(|e| e.path.starts_with("/ipfs/")) {
+            insert_bounded(cached, MAX_ENTRIES, None, &entry.path, entry.clone());
+        }
+    }
+
+    fn remember(&self, name: &str, target: &str) {
+        let names = &mut *self.names.lock().unwrap();
+        insert_bounded(names, MAX_NAMES, Some(NAME_TTL), name, target.to_string());
+    }
+}
+
+// Caches a value, dropping expired values and then the oldest once full.
+fn insert_bounded<V>(
+    map: &mut HashMap<String, (V, Instant)>,
+    max: usize,
+    ttl: Option<Duration>,
+    key: &str,
+    value: V,
+) {
+    if map.len() >= max {
+        if let Some(ttl) = ttl {
+            map.retain(|_, (_, at)| at.elapsed() < ttl);
+        }
+    }
+    if map.len() >= max {
+        let oldest = map
+            .iter()
+            .min_by_key(|(_, (_, at))| *at)
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            map.remove(&oldest);
+        }
+    }
+    map.insert(key.to_string(), (value, Instant::now()));
+}
+
+/// Whether a path is one of the namespaces themselves.
+#[inline]
+pub(super) fn is_namespace(path: &str) -> bool {
+    NAMESPACES
+        .iter()
+        .any(|ns| path.strip_prefix('/') == Some(ns))
+}
+
+/// Entry of a namespace, a directory that cannot be listed.
+pub(super) fn namespace_entry(path: &str) -> PeerEntry {
+    PeerEntry {
+        path: path.to_string(),
+        crtime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        is_dir: true,
+        size: 0,
+        cid: None,
+    }
+}
@@ -9,21 +9,38 @@
 #[macro_use]
 extern crate log;
 
-use webdav_handler::memls::MemLs;
-use webdav_handler::DavHandler;
-
 use crate::api::PeerApi;
-use crate::fs::PeerFs;
+pub use crate::buffer::WritePolicy;
+pub use crate::builder::IpfsWebDavBuilder;
+pub use crate::cache::{CachePolicy, CacheStats};
+pub use crate::error::BuildError;
+pub use crate::flush::{FlushPolicy, Flusher};
+pub use crate::server::{AuthHook, IpfsWebDav};
 
 pub mod api;
 
+mod buffer;
+mod builder;
 mod cache;
+mod dag;
+mod error;
+mod flush;
 mod fs;
+mod immutable;
+#[cfg(feature = "memory")]
+mod memory;
+mod props;
+mod reader;
+mod readonly;
+mod root;
+mod rpc;
+mod server;
+mod staging;
+mod times;
 
-/// Creates a WebDAV handler
-pub fn make_server(api: Box<dyn PeerApi>) -> DavHandler {
-    DavHandler::builder()
-        .filesystem(PeerFs::new(api))
-        .locksystem(MemLs::new())
-        .build_handler()
+/// Creates a WebDAV server with default settings, see `IpfsWebDavBuilder` for more control
+pub fn make_server(api: Box<dyn PeerApi>) -> IpfsWebDav {
+    IpfsWebDavBuilder::new(api)
+        .build()
+        .expect("the default settings are valid")
 }
@@ -0,0 +1,502 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+
+use crate::api::{PeerApi, PeerEntry, PeerKey, PeerStream};
+use crate::error::PeerError;
+
+/// An in-process `PeerApi` that keeps the whole MFS tree in memory.
+///
+/// It mirrors the semantics of the Kubo `files/*` RPCs closely enough to run
+/// `PeerFs` without an IPFS daemon, which makes it suitable for tests and for
+/// embedding scenarios where nothing has to outlive the process.
+///
+/// Content outside MFS is whatever some MFS node holds, so an `/ipfs/<cid>`
+/// path stays reachable only as long as a node with that CID is in the tree.
+/// IPNS names resolve to the paths set with `set_name` or published with the
+/// `self` key, whose name is `k51self`, or keys added with `add_key`.
+#[derive(Debug)]
+pub struct InMemoryApi {
+    tree: RwLock<BTreeMap<String, MemNode>>,
+    cids: Mutex<Option<Arc<Cids>>>,
+    names: RwLock<BTreeMap<String, String>>,
+    keys: RwLock<Vec<PeerKey>>,
+}
+
+/// Stand-in CIDs of all nodes, by path.
+type Cids = HashMap<String, String>;
+
+#[derive(Debug, Clone)]
+struct MemNode {
+    crtime: SystemTime,
+    mtime: SystemTime,
+    data: Option<Vec<u8>>,
+}
+
+impl InMemoryApi {
+    /// Creates an instance holding an empty root directory.
+    pub fn new() -> Box<InMemoryApi> {
+        let mut tree = BTreeMap::new();
+        tree.insert("/".to_string(), MemNode::new_dir());
+        Box::new(InMemoryApi {
+            tree: RwLock::new(tree),
+            cids: Mutex::new(None),
+            names: RwLock::new(BTreeMap::new()),
+            keys: RwLock::new(vec![PeerKey {
+                name: "self".to_string(),
+                id: "k51self".to_string(),
+            }]),
+        })
+    }
+
+    /// Adds a key to publish IPNS records with, named `k51<name>`.
+    pub fn add_key(&self, name: &str) -> PeerKey {
+        let key = PeerKey {
+            name: name.to_string(),
+            id: format!("k51{}", name),
+        };
+        self.keys.write().unwrap().push(key.clone());
+        key
+    }
+
+    /// Points an IPNS name at a path, usually `/ipfs/<cid>`.
+    pub fn set_name(&self, name: &str, path: &str) {
+        let names = &mut *self.names.write().unwrap();
+        names.insert(name.to_string(), path.to_string());
+    }
+
+    // The CIDs of the tree, computed once until it changes. Callers hold the tree
+    // lock, so the tree cannot change while they are computed.
+    fn cids(&self, tree: &BTreeMap<String, MemNode>) -> Arc<Cids> {
+        let cids = &mut *self.cids.lock().unwrap();
+        cids.get_or_insert_with(|| Arc::new(content_ids(tree)))
+            .clone()
+    }
+
+    // Drops the CIDs, called with the tree still locked for writing.
+    fn changed(&self) {
+        self.cids.lock().unwrap().take();
+    }
+}
+
+impl MemNode {
+    fn new_dir() -> Self {
+        let now = SystemTime::now();
+        MemNode {
+            crtime: now,
+            mtime: now,
+            data: None,
+        }
+    }
+
+    fn new_file() -> Self {
+        MemNode {
+            data: Some(Vec::new()),
+            ..MemNode::new_dir()
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.data.is_none()
+    }
+}
+
+fn to_entry(cids: &Cids, path: &str, node: &MemNode) -> PeerEntry {
+    PeerEntry {
+        path: path.to_string(),
+        crtime: node.crtime,
+        mtime: node.mtime,
+        is_dir: node.is_dir(),
+        size: node.data.as_ref().map_or(0, |d| d.len()),
+        cid: cids.get(path).cloned(),
+    }
+}
+
+// Stands in for CIDs: derived from names and contents only, so equal trees share
+// them. Like a DAG, every node hashes the names and CIDs of its children, which
+// sort after it, so a single pass from the back computes them all.
+fn content_ids(tree: &BTreeMap<String, MemNode>) -> Cids {
+    let mut cids = Cids::new();
+    let mut links: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    for (key, node) in tree.iter().rev() {
+        let mut hasher = DefaultHasher::new();
+        node.data.hash(&mut hasher);
+        if let Some(mut children) = links.remove(key) {
+            children.sort();
+            children.hash(&mut hasher);
+        }
+        let cid = format!("mem{:016x}", hasher.finish());
+        if key != "/" {
+            let link = (file_name(key).to_string(), cid.clone());
+            links.entry(parent_path(key)).or_default().push(link);
+        }
+        cids.insert(key.clone(), cid);
+    }
+    cids
+}
+
+#[async_trait]
+impl PeerApi for InMemoryApi {
+    async fn cp(&self, path: &str, dest: &str) -> Result<(), PeerError> {
+        let path = normalize_path(path)?;
+        let dest = target_path(&path, dest)?;
+        let tree = &mut *self.tree.write().unwrap();
+        let path = match path.starts_with("/ipfs/") {
+            true => resolve_ipfs(&self.cids(tree), tree, &path)?,
+            false => path,
+        };
+        if path == "/" {
+            return Err(PeerError::PermissionDenied);
+        }
+        if !tree.contains_key(&path) {
+            return Err(PeerError::NotFound);
+        }
+        check_parent(tree, &dest)?;
+        if tree.contains_key(&dest) {
+            return Err(PeerError::AlreadyExists);
+        }
+        for (key, node) in subtree(tree, &path) {
+            tree.insert(rebase(&key, &path, &dest), node);
+        }
+        self.changed();
+        Ok(())
+    }
+
+    async fn flush(&self, path: &str) -> Result<(), PeerError> {
+        let path = normalize_path(path)?;
+        let tree = self.tree.read().unwrap();
+        match tree.contains_key(&path) {
+            true => Ok(()),
+            false => Err(PeerError::NotFound),
+        }
+    }
+
+    async fn ls(&self, path: &str) -> Result<Vec<PeerEntry>, PeerError> {
+        let path = normalize_path(path)?;
+        let tree = self.tree.read().unwrap();
+        let node = tree.get(&path).ok_or(PeerError::NotFound)?;
+        let cids = self.cids(&tree);
+        if !node.is_dir() {
+            return Ok(vec![to_entry(&cids, &path, node)]);
+        }
+        Ok(children(&tree, &path)
+            .filter(|(k, _)| parent_path(k) == path)
+            .map(|(k, n)| to_entry(&cids, k, n))
+            .collect())
+    }
+
+    async fn mkdir(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        let path = normalize_path(path)?;
+        let tree = &mut *self.tree.write().unwrap();
+        check_parent(tree, &path)?;
+        if tree.contains_key(&path) {
+            return Err(PeerError::AlreadyExists);
+        }
+        tree.insert(path.clone(), MemNode::new_dir());
+        self.changed();
+        Ok(to_entry(&self.cids(tree), &path, &tree[&path]))
+    }
+
+    async fn mv(&self, path: &str, dest: &str) -> Result<(), PeerError> {
+        let path = normalize_path(path)?;
+        let mut dest = target_path(&path, dest)?;
+        let tree = &mut *self.tree.write().unwrap();
+        if path == "/" {
+            return Err(PeerError::PermissionDenied);
+        }
+        if !tree.contains_key(&path) {
+            return Err(PeerError::NotFound);
+        }
+        check_parent(tree, &dest)?;
+        // like MFS, moving onto a directory moves into it and onto a file replaces it
+        match tree.get(&dest) {
+            Some(node) if node.is_dir() => {
+                dest = join_path(&dest, file_name(&path));
+                if tree.contains_key(&dest) {
+                    return Err(PeerError::AlreadyExists);
+                }
+            }
+            Some(_) => {
+                tree.remove(&dest);
+                self.changed();
+            }
+            None => {}
+        }
+        if dest == path {
+            return Ok(());
+        }
+        if is_descendant(&dest, &path) {
+            return Err(PeerError::PermissionDenied);
+        }
+        for (key, node) in subtree(tree, &path) {
+            tree.remove(&key);
+            tree.insert(rebase(&key, &path, &dest), node);
+        }
+        self.changed();
+        Ok(())
+    }
+
+    async fn read(&self, path: &str, offset: usize, count: usize) -> Result<Bytes, PeerError> {
+        let path = normalize_path(path)?;
+        let tree = self.tree.read().unwrap();
+        let node = tree.get(&path).ok_or(PeerError::NotFound)?;
+        let data = node
+            .data
+            .as_ref()
+            .ok_or_else(|| PeerError::other(format!("{} was not a file", path)))?;
+        if offset > data.len() {
+            return Err(PeerError::other("offset was past end of file"));
+        }
+        let end = data.len().min(offset.saturating_add(count));
+        Ok(Bytes::copy_from_slice(&data[offset..end]))
+    }
+
+    async fn rm(&self, path: &str) -> Result<(), PeerError> {
+        let path = normalize_path(path)?;
+        let tree = &mut *self.tree.write().unwrap();
+        if path == "/" {
+            return Err(PeerError::PermissionDenied);
+        }
+        if !tree.contains_key(&path) {
+            return Err(PeerError::NotFound);
+        }
+        for (key, _) in subtree(tree, &path) {
+            tree.remove(&key);
+        }
+        self.changed();
+        Ok(())
+    }
+
+    async fn stat(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        let path = normalize_path(path)?;
+        let tree = self.tree.read().unwrap();
+        let node = tree.get(&path).ok_or(PeerError::NotFound)?;
+        Ok(to_entry(&self.cids(&tree), &path, node))
+    }
+
+    async fn write(
+        &self,
+        path: &str,
+        offset: usize,
+        truncate: bool,
+        data: Bytes,
+    ) -> Result<(), PeerError> {
+        let path = normalize_path(path)?;
+        let tree = &mut *self.tree.write().unwrap();
+        check_parent(tree, &path)?;
+        let node = tree.entry(path.clone()).or_insert_with(MemNode::new_file);
+        let content = node
+            .data
+            .as_mut()
+            .ok_or_else(|| PeerError::other(format!("{} was not a file", path)))?;
+        if truncate {
+            content.clear();
+        }
+        // like MFS, a gap left by writing past the end is filled with zeros
+        let end = offset + data.len();
+        if end > content.len() {
+            content.resize(end, 0);
+        }
+        content[offset..end].copy_from_slice(&data);
+        node.mtime = SystemTime::now();
+        self.changed();
+        Ok(())
+    }
+
+    async fn touch(&self, path: &str, mtime: SystemTime) -> Result<(), PeerError> {
+        let path = normalize_path(path)?;
+        let tree = &mut *self.tree.write().unwrap();
+        let node = tree.get_mut(&path).ok_or(PeerError::NotFound)?;
+        node.mtime = mtime;
+        Ok(())
+    }
+
+    async fn ipfs_ls(&self, path: &str) -> Result<Vec<PeerEntry>, PeerError> {
+        let path = normalize_path(path)?;
+        let tree = self.tree.read().unwrap();
+        let cids = self.cids(&tree);
+        let source = resolve_ipfs(&cids, &tree, &path)?;
+        let node = &tree[&source];
+        if !node.is_dir() {
+            let entry = to_entry(&cids, &source, node);
+            return Ok(vec![PeerEntry { path, ..entry }]);
+        }
+        Ok(children(&tree, &source)
+            .filter(|(k, _)| parent_path(k) == source)
+            .map(|(k, n)| to_entry(&cids, k, n))
+            .map(|entry| PeerEntry {
+                path: join_path(&path, file_name(&entry.path)),
+                ..entry
+            })
+            .collect())
+    }
+
+    async fn ipfs_stat(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        let path = normalize_path(path)?;
+        let tree = self.tree.read().unwrap();
+        let cids = self.cids(&tree);
+        let source = resolve_ipfs(&cids, &tree, &path)?;
+        let entry = to_entry(&cids, &source, &tree[&source]);
+        Ok(PeerEntry { path, ..entry })
+    }
+
+    async fn cat(&self, path: &str, offset: usize) -> Result<PeerStream, PeerError> {
+        let source = {
+            let tree = self.tree.read().unwrap();
+            resolve_ipfs(&self.cids(&tree), &tree, &normalize_path(path)?)?
+        };
+        let data = self.read(&source, offset, usize::MAX).await?;
+        Ok(stream::once(async { Ok(data) }).boxed())
+    }
+
+    async fn resolve_name(&self, name: &str) -> Result<String, PeerError> {
+        let names = self.names.read().unwrap();
+        names.get(name).cloned().ok_or(PeerError::NotFound)
+    }
+
+    async fn key_list(&self) -> Result<Vec<PeerKey>, PeerError> {
+        Ok(self.keys.read().unwrap().clone())
+    }
+
+    async fn name_publish(&self, path: &str, key: &str) -> Result<String, PeerError> {
+        let keys = self.keys.read().unwrap();
+        let key = keys
+            .iter()
+            .find(|k| k.name == key)
+            .ok_or(PeerError::NotFound)?;
+        self.set_name(&key.id, path);
+        Ok(key.id.clone())
+    }
+}
+
+// Finds the MFS node holding the content of an `/ipfs/<cid>/...` path.
+fn resolve_ipfs(
+    cids: &Cids,
+    tree: &BTreeMap<String, MemNode>,
+    path: &str,
+) -> Result<String, PeerError> {
+    let rest = path.strip_prefix("/ipfs/").ok_or(PeerError::NotFound)?;
+    let (cid, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    // the first path in key order, as equal trees share CIDs
+    let root = tree
+        .keys()
+        .find(|key| cids.get(*key).is_some_and(|c| c == cid))
+        .ok_or(PeerError::NotFound)?;
+    let source = match (root.as_str(), rest) {
+        (root, "") => root.to_string(),
+        ("/", rest) => rest.to_string(),
+        (root, rest) => format!("{}{}", root, rest),
+    };
+    match tree.contains_key(&source) {
+        true => Ok(source),
+        false => Err(PeerError::NotFound),
+    }
+}
+
+// Collects a node and all of its descendants.
+fn subtree(tree: &BTreeMap<String, MemNode>, path: &str) -> Vec<(String, MemNode)> {
+    tree.get_key_value(path)
+        .into_iter()
+        .chain(children(tree, path))
+        .map(|(k, n)| (k.clone(), n.clone()))
+        .collect()
+}
+
+// Iterates over all descendants of a node in key order.
+fn children<'a>(
+    tree: &'a BTreeMap<String, MemNode>,
+    path: &str,
+) -> impl Iterator<Item = (&'a String, &'a MemNode)> {
+    let prefix = add_slash(path);
+    let len = prefix.len();
+    tree.range(prefix.clone()..)
+        .take_while(move |(k, _)| k.starts_with(&prefix))
+        .filter(move |(k, _)| k.len() > len)
+}
+
+fn check_parent(tree: &BTreeMap<String, MemNode>, path: &str) -> Result<(), PeerError> {
+    if path == "/" {
+        return Err(PeerError::AlreadyExists);
+    }
+    match tree.get(&parent_path(path)) {
+        None => Err(PeerError::NotFound),
+        Some(parent) if !parent.is_dir() => Err(PeerError::PermissionDenied),
+        Some(_) => Ok(()),
+    }
+}
+
+#[inline]
+fn normalize_path(path: &str) -> Result<String, PeerError> {
+    if !path.starts_with('/') {
+        return Err(PeerError::other("paths must start with a leading slash"));
+    }
+    let mut path = path.to_string();
+    while path.len() > 1 && path.ends_with('/') {
+        path.pop();
+    }
+    Ok(path)
+}
+
+// A destination ending with a slash names a directory to put the source into.
+#[inline]
+fn target_path(path: &str, dest: &str) -> Result<String, PeerError> {
+    match dest.ends_with('/') {
+        true => Ok(join_path(&normalize_path(dest)?, file_name(path))),
+        false => normalize_path(dest),
+    }
+}
+
+#[inline]
+fn is_descendant(key: &str, path: &str) -> bool {
+    key.starts_with(&add_slash(path))
+}
+
+#[inline]
+fn rebase(key: &str, from: &str, to: &str) -> String {
+    format!("{}{}", to, &key[from.len()..])
+}
+
+#[inline]
+fn join_path(dir: &str, name: &str) -> String {
+    format!("{}{}", add_slash(dir), name)
+}
+
+#[inline]
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+}
+
+#[inline]
+fn parent_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+#[inline]
+fn add_slash(path: &str) -> String {
+    let mut path = path.to_string();
+    if !path.ends_with('/') {
+        path.push('/');
+    }
+    path
+}
@@ -0,0 +1,245 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::future;
+use serde::{Deserialize, Serialize};
+use webdav_handler::fs::DavProp;
+
+use crate::api::{PeerApi, PeerError};
+use crate::flush::{FlushPolicy, Flusher};
+
+/// MFS directory holding the data `ipfs-webdav` keeps next to the served files.
+pub(super) const META_DIR: &str = "/.ipfs-webdav";
+
+const PROPS_DIR: &str = "/.ipfs-webdav/props";
+
+const PROPS_FILE: &str = "props.json";
+
+/// Dead properties persisted in MFS.
+///
+/// The properties of a path live in a JSON file inside a sidecar tree that mirrors
+/// the served one, so a MOVE, COPY or DELETE can apply the same MFS operation to
+/// the sidecar. Mirrored names get a `@` prefix to never clash with the JSON file.
+/// Saved properties are flushed like uploads, following the `FlushPolicy`.
+#[derive(Debug, Clone)]
+pub(super) struct PropStore {
+    api: Arc<Box<dyn PeerApi>>,
+    flusher: Flusher,
+    flush_policy: FlushPolicy,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProp {
+    name: String,
+    prefix: Option<String>,
+    namespace: Option<String>,
+    xml: Option<String>,
+}
+
+impl PropStore {
+    pub(super) fn new(
+        api: Arc<Box<dyn PeerApi>>,
+        flusher: Flusher,
+        flush_policy: FlushPolicy,
+    ) -> Self {
+        PropStore {
+            api,
+            flusher,
+            flush_policy,
+        }
+    }
+
+    /// Loads the properties of the children of a directory that have any.
+    ///
+    /// The sidecar of the directory is listed once, so only the children that
+    /// have a sidecar of their own are read, all at the same time.
+    pub(super) async fn load_children(
+        &self,
+        dir: &str,
+    ) -> Result<HashMap<String, HashMap<String, DavProp>>, PeerError> {
+        let entries = match self.api.ls(&sidecar(dir)).await {
+            Ok(entries) => entries,
+            Err(PeerError::NotFound) => return Ok(HashMap::new()),
+            Err(e) => return Err(e),
+        };
+        let names: Vec<String> = entries
+            .iter()
+            .filter(|e| e.is_dir)
+            .filter_map(|e| Path::new(&e.path).file_name()?.to_str()?.strip_prefix('@'))
+            .map(|name| name.to_string())
+            .collect();
+        let dir = dir.trim_end_matches('/');
+        let paths: Vec<String> = names.iter().map(|n| format!("{}/{}", dir, n)).collect();
+        let props = future::try_join_all(paths.iter().map(|p| self.load(p))).await?;
+        Ok(names
+            .into_iter()
+            .zip(props)
+            .filter(|(_, props)| !props.is_empty())
+            .collect())
+    }
+
+    /// Loads the properties of a path, which has none if it was never patched.
+    pub(super) async fn load(&self, path: &str) -> Result<HashMap<String, DavProp>, PeerError> {
+        let file = props_file(path);
+        let data = match self.api.read(&file, 0, i64::MAX as usize).await {
+            Ok(data) => data,
+            Err(PeerError::NotFound) => return Ok(HashMap::new()),
+            Err(e) => return Err(e),
+        };
+        let stored: Vec<StoredProp> = match serde_json::from_slice(&data) {
+            Ok(stored) => stored,
+            Err(e) => {
+                warn!("Ignoring unreadable properties {}: {}", file, e);
+                return Ok(HashMap::new());
+            }
+        };
+        Ok(stored
+            .into_iter()
+            .map(|p| {
+                let prop = DavProp {
+                    name: p.name,
+                    prefix: p.prefix,
+                    namespace: p.namespace,
+                    xml: p.xml.map(String::into_bytes),
+                };
+                (prop_key(&prop), prop)
+            })
+            .collect())
+    }
+
+    /// Copies the properties of a path, but not of what is below it.
+    pub(super) async fn cp_own(&self, from: &str, to: &str) -> Result<(), PeerError> {
+        let props = self.load(from).await?;
+        match props.is_empty() {
+            true => Ok(()),
+            false => self.save(to, &props).await,
+        }
+    }
+
+    /// Replaces the stored properties of a path.
+    pub(super) async fn save(
+        &self,
+        path: &str,
+        props: &HashMap<String, DavProp>,
+    ) -> Result<(), PeerError> {
+        let file = props_file(path);
+        if props.is_empty() {
+            return ignore_missing(self.api.rm(&file).await);
+        }
+        let stored: Vec<StoredProp> = props
+            .values()
+            .map(|p| StoredProp {
+                name: p.name.clone(),
+                prefix: p.prefix.clone(),
+                namespace: p.namespace.clone(),
+                xml: p
+                    .xml
+                    .as_ref()
+                    .map(|x| String::from_utf8_lossy(x).into_owned()),
+            })
+            .collect();
+        let data = Bytes::from(serde_json::to_vec(&stored).map_err(PeerError::other)?);
+        match self.api.write(&file, 0, true, data.clone()).await {
+            Err(PeerError::NotFound) => {
+                self.mkdir_all(&sidecar(path)).await?;
+                self.api.write(&file, 0, true, data).await?;
+            }
+            res => res?,
+        }
+        self.flusher.closed(&file, self.flush_policy).await
+    }
+
+    /// Moves the properties of a path and everything below it.
+    pub(super) async fn mv(&self, from: &str, to: &str) -> Result<(), PeerError> {
+        match self.prepare_dest(from, to).await? {
+            true => ignore_missing(self.api.mv(&sidecar(from), &sidecar(to)).await),
+            false => Ok(()),
+        }
+    }
+
+    /// Copies the properties of a path and everything below it.
+    pub(super) async fn cp(&self, from: &str, to: &str) -> Result<(), PeerError> {
+        match self.prepare_dest(from, to).await? {
+            true => ignore_missing(self.api.cp(&sidecar(from), &sidecar(to)).await),
+            false => Ok(()),
+        }
+    }
+
+    /// Drops the properties of a path and everything below it.
+    pub(super) async fn rm(&self, path: &str) -> Result<(), PeerError> {
+        ignore_missing(self.api.rm(&sidecar(path)).await)
+    }
+
+    // Clears whatever an overwritten destination left behind and, if the source
+    // has a sidecar, creates the parents of the destination one. Returns whether
+    // there is a sidecar to move or copy.
+    async fn prepare_dest(&self, from: &str, to: &str) -> Result<bool, PeerError> {
+        let dest = sidecar(to);
+        ignore_missing(self.api.rm(&dest).await)?;
+        match self.api.stat(&sidecar(from)).await {
+            Ok(_) => {}
+            Err(PeerError::NotFound) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        if let Some(parent) = Path::new(&dest).parent().and_then(|p| p.to_str()) {
+            self.mkdir_all(parent).await?;
+        }
+        Ok(true)
+    }
+
+    async fn mkdir_all(&self, dir: &str) -> Result<(), PeerError> {
+        let mut path = String::new();
+        for name in dir.split('/').filter(|s| !s.is_empty()) {
+            path = format!("{}/{}", path, name);
+            match self.api.mkdir(&path).await {
+                Ok(_) | Err(PeerError::AlreadyExists) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a path belongs to the data `ipfs-webdav` keeps for itself.
+#[inline]
+pub(super) fn is_hidden(path: &str) -> bool {
+    path.strip_prefix(META_DIR)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[inline]
+pub(super) fn prop_key(p: &DavProp) -> String {
+    p.namespace.clone().unwrap_or_default() + &p.name
+}
+
+#[inline]
+fn sidecar(path: &str) -> String {
+    path.split('/')
+        .filter(|s| !s.is_empty())
+        .fold(PROPS_DIR.to_string(), |dir, name| {
+            format!("{}/@{}", dir, name)
+        })
+}
+
+#[inline]
+fn props_file(path: &str) -> String {
+    format!("{}/{}", sidecar(path), PROPS_FILE)
+}
+
+#[inline]
+fn ignore_missing(res: Result<(), PeerError>) -> Result<(), PeerError> {
+    match res {
+        Err(PeerError::NotFound) => Ok(()),
+        res => res,
+    }
+}
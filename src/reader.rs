@@ -0,0 +1,72 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use bytes::Bytes;
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::api::{PeerError, PeerStream};
+
+/// Sequential reader on top of a single `files/read` stream.
+///
+/// A background task keeps pulling chunks from the node into a bounded channel,
+/// so the next chunk is usually there by the time a client asks for it. Reads
+/// hand out slices of the received chunks without copying them.
+#[derive(Debug)]
+pub(super) struct ReadAhead {
+    pos: usize,
+    pending: Bytes,
+    chunks: mpsc::Receiver<Result<Bytes, PeerError>>,
+    task: JoinHandle<()>,
+}
+
+impl ReadAhead {
+    /// Starts prefetching up to `depth` chunks of a stream that begins at `pos`.
+    pub(super) fn new(mut stream: PeerStream, pos: usize, depth: usize) -> Self {
+        let (tx, chunks) = mpsc::channel(depth.max(1));
+        let task = tokio::spawn(async move {
+            while let Some(chunk) = stream.next().await {
+                let failed = chunk.is_err();
+                if tx.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        ReadAhead {
+            pos,
+            pending: Bytes::new(),
+            chunks,
+            task,
+        }
+    }
+
+    /// File offset of the next byte this reader returns.
+    pub(super) fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Reads up to `count` bytes, returning fewer at the end of the file.
+    pub(super) async fn read(&mut self, count: usize) -> Result<Bytes, PeerError> {
+        while self.pending.is_empty() {
+            match self.chunks.recv().await {
+                Some(chunk) => self.pending = chunk?,
+                None => return Ok(Bytes::new()),
+            }
+        }
+        let data = self.pending.split_to(count.min(self.pending.len()));
+        self.pos += data.len();
+        Ok(data)
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
@@ -0,0 +1,50 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use webdav_handler::fs::{FsError, FsResult};
+
+use crate::dag::is_within;
+use crate::error::BuildError;
+use crate::root::ShareRoot;
+
+/// MFS trees that WebDAV clients may read but not change.
+///
+/// Collections are given as paths of the share and kept as the MFS paths below
+/// the share root they stand for.
+#[derive(Debug, Clone, Default)]
+pub(super) struct ReadOnly {
+    all: bool,
+    collections: Vec<String>,
+}
+
+impl ReadOnly {
+    pub(super) fn new(
+        all: bool,
+        collections: Vec<String>,
+        root: &ShareRoot,
+    ) -> Result<Self, BuildError> {
+        let collections = collections
+            .iter()
+            .map(|p| root.join(p))
+            .collect::<Result<_, _>>()?;
+        Ok(ReadOnly { all, collections })
+    }
+
+    /// Whether the whole share is read-only.
+    pub(super) fn is_all(&self) -> bool {
+        self.all
+    }
+
+    /// Refuses changes to a path inside a read-only tree.
+    pub(super) fn check(&self, path: &str) -> FsResult<()> {
+        match self.all || self.collections.iter().any(|dir| is_within(path, dir)) {
+            true => Err(FsError::Forbidden),
+            false => Ok(()),
+        }
+    }
+}
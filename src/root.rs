@@ -0,0 +1,133 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::path::Component;
+use std::sync::Arc;
+
+use tokio::sync::OnceCell;
+use webdav_handler::davpath::DavPath;
+use webdav_handler::fs::{FsError, FsResult};
+
+use crate::api::{PeerApi, PeerError};
+use crate::error::BuildError;
+
+/// MFS directory served as the root of the share.
+///
+/// WebDAV paths are mapped below it and can never leave it: webdav-handler
+/// resolves `..` segments before it decodes the path, so a percent-encoded
+/// `%2e%2e` still arrives here and is refused.
+#[derive(Debug, Clone)]
+pub(super) struct ShareRoot {
+    path: String,
+    started: bool,
+    ready: Arc<OnceCell<()>>,
+}
+
+impl ShareRoot {
+    /// Creates the root for an absolute MFS path, `/` for the whole MFS.
+    pub(super) fn new(path: &str) -> Result<Self, BuildError> {
+        Ok(ShareRoot {
+            path: join_path(String::new(), path)?,
+            started: false,
+            ready: Arc::new(OnceCell::new()),
+        })
+    }
+
+    pub(super) fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Maps a WebDAV path to the MFS path it stands for.
+    pub(super) fn mfs_path(&self, path: &DavPath) -> FsResult<String> {
+        let base = match self.path.as_str() {
+            "/" => String::new(),
+            root => root.to_string(),
+        };
+        join_dav_path(base, path)
+    }
+
+    /// Maps a path of the share given in the settings to the MFS path it stands for.
+    pub(super) fn join(&self, path: &str) -> Result<String, BuildError> {
+        match self.path.as_str() {
+            "/" => join_path(String::new(), path),
+            root => join_path(root.to_string(), path),
+        }
+    }
+
+    /// Creates the root directory in the background, if there is a runtime.
+    pub(super) fn start(&mut self, api: Arc<Box<dyn PeerApi>>) {
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => runtime,
+            Err(_) => return,
+        };
+        self.started = true;
+        let root = self.clone();
+        runtime.spawn(async move {
+            if let Err(e) = root.prepare(api.as_ref().as_ref()).await {
+                warn!("Failed to create share root {}: {}", root.path, e);
+            }
+        });
+    }
+
+    /// Waits for the root directory to be created, if it was started.
+    pub(super) async fn ready(&self, api: &dyn PeerApi) -> Result<(), PeerError> {
+        match self.started {
+            true => self.prepare(api).await,
+            false => Ok(()),
+        }
+    }
+
+    /// Creates the root directory and its parents, unless that already happened.
+    pub(super) async fn prepare(&self, api: &dyn PeerApi) -> Result<(), PeerError> {
+        self.ready
+            .get_or_try_init(|| async {
+                let mut dir = String::new();
+                for name in self.path.split('/').filter(|s| !s.is_empty()) {
+                    dir = format!("{}/{}", dir, name);
+                    match api.mkdir(&dir).await {
+                        Ok(_) | Err(PeerError::AlreadyExists) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+// Appends the segments of a configured path to a base path, refusing `..`.
+fn join_path(mut base: String, path: &str) -> Result<String, BuildError> {
+    for name in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+        if name == ".." {
+            return Err(BuildError::InvalidPath(path.to_string()));
+        }
+        base.push('/');
+        base.push_str(name);
+    }
+    if base.is_empty() {
+        base.push('/');
+    }
+    Ok(base)
+}
+
+/// Appends the segments of a WebDAV path to a base path, refusing anything but plain names.
+pub(super) fn join_dav_path(mut base: String, path: &DavPath) -> FsResult<String> {
+    for component in path.as_rel_ospath().components() {
+        let name = match component {
+            Component::Normal(name) => name.to_str().ok_or(FsError::Forbidden)?,
+            _ => return Err(FsError::Forbidden),
+        };
+        base.push('/');
+        base.push_str(name);
+    }
+    if base.is_empty() {
+        base.push('/');
+    }
+    Ok(base)
+}
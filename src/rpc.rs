@@ -0,0 +1,117 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+//! Kubo RPC requests and responses that `ipfs-api` does not model (fully).
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ipfs_api_backend_hyper::request::ApiRequest;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+pub(super) struct FilesStat<'a> {
+    #[serde(rename = "arg")]
+    pub path: &'a str,
+}
+
+impl<'a> ApiRequest for FilesStat<'a> {
+    const PATH: &'static str = "/files/stat";
+}
+
+#[derive(Serialize)]
+pub(super) struct FilesLs<'a> {
+    #[serde(rename = "arg")]
+    pub path: &'a str,
+
+    pub long: bool,
+}
+
+impl<'a> ApiRequest for FilesLs<'a> {
+    const PATH: &'static str = "/files/ls";
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(super) struct FilesTouch<'a> {
+    #[serde(rename = "arg")]
+    pub path: &'a str,
+
+    pub mtime: i64,
+
+    pub mtime_nsecs: u32,
+
+    pub flush: bool,
+}
+
+impl<'a> ApiRequest for FilesTouch<'a> {
+    const PATH: &'static str = "/files/touch";
+}
+
+/// Response of `files/stat`, including the UnixFS 1.5 metadata newer nodes report.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(super) struct FilesStatResponse {
+    pub hash: String,
+
+    pub size: u64,
+
+    #[serde(rename = "Type")]
+    pub typ: String,
+
+    pub mtime: Option<i64>,
+
+    pub mtime_nsecs: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(super) struct FilesEntry {
+    pub name: String,
+
+    #[serde(rename = "Type")]
+    pub typ: u64,
+
+    pub size: u64,
+
+    pub hash: String,
+
+    pub mtime: Option<i64>,
+
+    pub mtime_nsecs: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(super) struct FilesLsResponse {
+    #[serde(deserialize_with = "null_as_default")]
+    pub entries: Vec<FilesEntry>,
+}
+
+/// Converts UnixFS mtime fields into a `SystemTime`, if the node reported one.
+pub(super) fn to_system_time(secs: Option<i64>, nsecs: Option<u32>) -> Option<SystemTime> {
+    match secs {
+        Some(secs) if secs > 0 => {
+            Some(UNIX_EPOCH + Duration::new(secs as u64, nsecs.unwrap_or_default()))
+        }
+        _ => None,
+    }
+}
+
+/// Splits a `SystemTime` into UnixFS mtime fields.
+pub(super) fn from_system_time(time: SystemTime) -> (i64, u32) {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    (since.as_secs() as i64, since.subsec_nanos())
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
@@ -0,0 +1,138 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::Arc;
+
+use bytes::Buf;
+use futures::future::BoxFuture;
+use http::request::Parts;
+use http::{Request, Response, StatusCode, Uri};
+use http_body::Body as HttpBody;
+use webdav_handler::body::Body;
+use webdav_handler::davpath::DavPath;
+use webdav_handler::DavHandler;
+
+use crate::fs::PeerFs;
+
+/// Decides whether a request may reach the WebDAV handler.
+///
+/// Returns the principal the request acts as, which becomes the owner of the
+/// locks it takes, or the response to send instead, e.g. a `401` with a
+/// `WWW-Authenticate` challenge. The hook is async, so it can ask a token
+/// service before it answers.
+pub type AuthHook = Arc<
+    dyn for<'a> Fn(&'a Parts) -> BoxFuture<'a, Result<Option<String>, Box<Response<Body>>>>
+        + Send
+        + Sync,
+>;
+
+/// WebDAV server of a share, which authenticates requests before handling them
+/// when it has an auth hook.
+#[derive(Clone)]
+pub struct IpfsWebDav {
+    handler: DavHandler,
+    fs: Box<PeerFs>,
+    prefix: String,
+    auth: Option<AuthHook>,
+}
+
+// Source and destination of a COPY request, whose collections need their
+// properties copied once the handler is done.
+struct Copy {
+    from: DavPath,
+    to: DavPath,
+    deep: bool,
+}
+
+impl IpfsWebDav {
+    pub(super) fn new(
+        handler: DavHandler,
+        fs: Box<PeerFs>,
+        prefix: String,
+        auth: Option<AuthHook>,
+    ) -> Self {
+        IpfsWebDav {
+            handler,
+            fs,
+            prefix,
+            auth,
+        }
+    }
+
+    /// Returns the handler serving the requests that pass authentication, which
+    /// skips the auth hook.
+    pub fn handler(&self) -> &DavHandler {
+        &self.handler
+    }
+
+    /// Handles a request once the auth hook, if any, accepted it.
+    pub async fn handle<ReqBody, ReqData, ReqError>(&self, req: Request<ReqBody>) -> Response<Body>
+    where
+        ReqData: Buf + Send + 'static,
+        ReqError: StdError + Send + Sync + 'static,
+        ReqBody: HttpBody<Data = ReqData, Error = ReqError>,
+    {
+        let (parts, body) = req.into_parts();
+        let principal = match &self.auth {
+            Some(auth) => match auth(&parts).await {
+                Ok(principal) => principal,
+                Err(res) => return *res,
+            },
+            None => None,
+        };
+        let copy = self.copy(&parts);
+        let req = Request::from_parts(parts, body);
+        let res = match principal {
+            Some(principal) => {
+                let config = DavHandler::builder().principal(principal);
+                self.handler.handle_with(config, req).await
+            }
+            None => self.handler.handle(req).await,
+        };
+        if let Some(copy) = copy {
+            if matches!(res.status(), StatusCode::CREATED | StatusCode::NO_CONTENT) {
+                let res = self
+                    .fs
+                    .copy_collection_props(&copy.from, &copy.to, copy.deep)
+                    .await;
+                if let Err(e) = res {
+                    warn!("Failed to copy the properties of {}: {:?}", copy.from, e);
+                }
+            }
+        }
+        res
+    }
+
+    // The handler validates the request, so anything it cannot make sense of
+    // is left to it.
+    fn copy(&self, parts: &Parts) -> Option<Copy> {
+        if parts.method.as_str() != "COPY" {
+            return None;
+        }
+        let mut from = DavPath::new(parts.uri.path()).ok()?;
+        from.set_prefix(&self.prefix).ok()?;
+        let dest = parts.headers.get("Destination")?.to_str().ok()?;
+        let mut to = match dest.starts_with('/') {
+            true => DavPath::new(dest).ok()?,
+            false => DavPath::new(dest.parse::<Uri>().ok()?.path()).ok()?,
+        };
+        to.set_prefix(&self.prefix).ok()?;
+        let deep = parts.headers.get("Depth").is_none_or(|d| d != "0");
+        Some(Copy { from, to, deep })
+    }
+}
+
+impl fmt::Debug for IpfsWebDav {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IpfsWebDav")
+            .field("auth", &self.auth.is_some())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::OnceCell;
+
+use crate::api::{PeerApi, PeerError};
+use crate::props::META_DIR;
+
+const STAGING_DIR: &str = "/.ipfs-webdav/uploads";
+
+/// Age after which no upload is expected to still write to its staging file.
+const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Number of staging areas created by this process, which tells them apart.
+static INSTANCES: AtomicU64 = AtomicU64::new(0);
+
+/// Hidden MFS directory that atomic uploads are written to before they are
+/// moved over their destination.
+///
+/// Several servers may share a node and its staging directory, so staging files
+/// are named after their creation time, the process and the instance staging
+/// them. Uploads that never got flushed leave their staging files behind, so on
+/// startup the entries older than a day are removed.
+#[derive(Debug, Clone)]
+pub(super) struct Staging {
+    api: Arc<Box<dyn PeerApi>>,
+    instance: String,
+    ready: Arc<OnceCell<()>>,
+    next: Arc<AtomicU64>,
+}
+
+impl Staging {
+    pub(super) fn new(api: Arc<Box<dyn PeerApi>>) -> Self {
+        let instance = INSTANCES.fetch_add(1, Ordering::Relaxed);
+        Staging {
+            api,
+            instance: format!("{:x}-{}", process::id(), instance),
+            ready: Arc::new(OnceCell::new()),
+            next: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Prepares the staging directory in the background, if there is a runtime.
+    pub(super) fn start(&self) {
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => runtime,
+            Err(_) => return,
+        };
+        let staging = self.clone();
+        runtime.spawn(async move {
+            if let Err(e) = staging.ready.get_or_try_init(|| staging.prepare()).await {
+                warn!("Failed to prepare staging directory {}: {}", STAGING_DIR, e);
+            }
+        });
+    }
+
+    /// Picks a staging path no other upload uses.
+    pub(super) async fn path(&self) -> Result<String, PeerError> {
+        self.ready.get_or_try_init(|| self.prepare()).await?;
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        Ok(format!(
+            "{}/{:x}-{}-{}",
+            STAGING_DIR,
+            now(),
+            self.instance,
+            n
+        ))
+    }
+
+    // Makes sure the directory exists and drops stale staging files.
+    async fn prepare(&self) -> Result<(), PeerError> {
+        for dir in [META_DIR, STAGING_DIR] {
+            match self.api.mkdir(dir).await {
+                Ok(_) | Err(PeerError::AlreadyExists) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        for entry in self.api.ls(STAGING_DIR).await? {
+            // directories are left by versions that staged per instance
+            if is_stale(&entry.path) {
+                self.remove(&entry.path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, path: &str) -> Result<(), PeerError> {
+        match self.api.rm(path).await {
+            Ok(_) => debug!("Removed stale staging file {}", path),
+            Err(PeerError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+}
+
+// Whether a staging entry was created longer ago than `STALE_AFTER`, judging by its name.
+fn is_stale(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let created = name.split('-').next().unwrap_or_default();
+    match u128::from_str_radix(created, 16) {
+        Ok(created) => now().saturating_sub(created) > STALE_AFTER.as_nanos(),
+        Err(_) => true,
+    }
+}
+
+#[inline]
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
@@ -0,0 +1,197 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+/// Time during which changes are collected before they are written together.
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Timestamps of MFS entities that the IPFS node does not keep itself.
+///
+/// MFS has no notion of creation time, and nodes without UnixFS 1.5 support
+/// don't track modification time either. Both are recorded here when a path is
+/// written through this store's API, dropped along with the path, and
+/// optionally persisted to a local JSON file. Reading never adds an entry.
+///
+/// The file is written in the background, at most once per `SAVE_DELAY`, so a
+/// bulk COPY or MOVE rewrites it once rather than for every path it touches.
+#[derive(Debug, Default)]
+pub(super) struct TimeStore {
+    file: Option<PathBuf>,
+    state: Arc<Mutex<State>>,
+    writing: Arc<Mutex<()>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    times: HashMap<String, Times>,
+    dirty: bool,
+    scheduled: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(super) struct Times {
+    pub crtime: SystemTime,
+    pub mtime: SystemTime,
+}
+
+impl TimeStore {
+    /// Creates a store persisted to the given file, loading what it already holds.
+    pub(super) fn open(file: PathBuf) -> Self {
+        let times = match fs::read(&file) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable time store {:?}: {}", file, e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        TimeStore {
+            file: Some(file),
+            state: Arc::new(Mutex::new(State {
+                times,
+                ..Default::default()
+            })),
+            writing: Arc::default(),
+        }
+    }
+
+    /// Returns the timestamps recorded for a path, if it was ever written.
+    pub(super) fn get(&self, path: &str) -> Option<Times> {
+        self.state.lock().unwrap().times.get(path).copied()
+    }
+
+    /// Sets the modification time of a path.
+    pub(super) fn touch(&self, path: &str, mtime: SystemTime) {
+        let state = &mut *self.state.lock().unwrap();
+        state
+            .times
+            .entry(path.to_string())
+            .and_modify(|t| t.mtime = mtime)
+            .or_insert(Times {
+                crtime: mtime,
+                mtime,
+            });
+        state.dirty = true;
+    }
+
+    pub(super) fn mv(&self, from: &str, to: &str) {
+        let state = &mut *self.state.lock().unwrap();
+        let moved = take_prefixed(&mut state.times, from);
+        state.dirty |= !moved.is_empty();
+        for (key, times) in moved {
+            state.times.insert(rebase(&key, from, to), times);
+        }
+    }
+
+    pub(super) fn cp(&self, from: &str, to: &str) {
+        let state = &mut *self.state.lock().unwrap();
+        let now = SystemTime::now();
+        let copied = take_prefixed(&mut state.times, from);
+        state.dirty |= !copied.is_empty();
+        for (key, times) in copied {
+            let copy = Times {
+                crtime: now,
+                ..times
+            };
+            state.times.insert(rebase(&key, from, to), copy);
+            state.times.insert(key, times);
+        }
+    }
+
+    pub(super) fn rm(&self, path: &str) {
+        let state = &mut *self.state.lock().unwrap();
+        state.dirty |= !take_prefixed(&mut state.times, path).is_empty();
+    }
+
+    /// Schedules writing pending changes to the backing file, if there is one.
+    ///
+    /// Without a runtime to write in the background, they are written right away.
+    pub(super) fn save(&self) {
+        let file = match &self.file {
+            Some(file) => file.clone(),
+            None => return,
+        };
+        let runtime = tokio::runtime::Handle::try_current().ok();
+        {
+            let state = &mut *self.state.lock().unwrap();
+            if !state.dirty || state.scheduled {
+                return;
+            }
+            state.scheduled = runtime.is_some();
+        }
+        let (state, writing) = (self.state.clone(), self.writing.clone());
+        match runtime {
+            Some(runtime) => {
+                runtime.spawn(async move {
+                    tokio::time::sleep(SAVE_DELAY).await;
+                    let write = move || write(&file, &state, &writing);
+                    if let Err(e) = tokio::task::spawn_blocking(write).await {
+                        warn!("Failed to save time store: {}", e);
+                    }
+                });
+            }
+            None => write(&file, &state, &writing),
+        }
+    }
+}
+
+// Whatever is still pending is written before the store goes away.
+impl Drop for TimeStore {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            write(file, &self.state, &self.writing);
+        }
+    }
+}
+
+// Writes the times if they changed since the last write, one writer at a time.
+fn write(file: &Path, state: &Mutex<State>, writing: &Mutex<()>) {
+    let _writing = writing.lock().unwrap();
+    let times = {
+        let state = &mut *state.lock().unwrap();
+        state.scheduled = false;
+        if !state.dirty {
+            return;
+        }
+        state.dirty = false;
+        state.times.clone()
+    };
+    let tmp = file.with_extension("tmp");
+    let res = serde_json::to_vec(&times)
+        .map_err(std::io::Error::from)
+        .and_then(|data| fs::write(&tmp, data))
+        .and_then(|_| fs::rename(&tmp, file));
+    if let Err(e) = res {
+        warn!("Failed to save time store {:?}: {}", file, e);
+        state.lock().unwrap().dirty = true;
+    }
+}
+
+// Removes a path and everything below it, returning the removed entries.
+fn take_prefixed(times: &mut HashMap<String, Times>, path: &str) -> Vec<(String, Times)> {
+    let prefix = format!("{}/", path.trim_end_matches('/'));
+    let keys: Vec<String> = times
+        .keys()
+        .filter(|k| k.as_str() == path || k.starts_with(&prefix))
+        .cloned()
+        .collect();
+    keys.into_iter()
+        .filter_map(|k| times.remove(&k).map(|t| (k, t)))
+        .collect()
+}
+
+#[inline]
+fn rebase(key: &str, from: &str, to: &str) -> String {
+    format!("{}{}", to, &key[from.len()..])
+}
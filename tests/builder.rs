@@ -0,0 +1,262 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+mod common;
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use common::{get, mkcol, propfind, put, request, RecordingApi};
+use futures::FutureExt;
+use http::{Request, Response, StatusCode};
+use ipfs_webdav::api::{InMemoryApi, PeerApi, PeerError};
+use ipfs_webdav::{BuildError, IpfsWebDav, IpfsWebDavBuilder, WritePolicy};
+use webdav_handler::body::Body;
+use webdav_handler::fakels::FakeLs;
+
+async fn handle(server: &IpfsWebDav, method: &str, path: &str, auth: Option<&str>) -> StatusCode {
+    let mut req = Request::builder().method(method).uri(path);
+    if let Some(auth) = auth {
+        req = req.header("Authorization", auth);
+    }
+    let req = req.body(hyper::Body::from("x")).unwrap();
+    server.handle(req).await.status()
+}
+
+fn authenticated_server() -> IpfsWebDav {
+    IpfsWebDavBuilder::new(InMemoryApi::new())
+        .auth(|req| {
+            async move {
+                // stands in for a call to a token service
+                tokio::task::yield_now().await;
+                match req.headers.get("Authorization") {
+                    Some(value) if value == "Bearer secret" => Ok(Some("alice".to_string())),
+                    _ => Err(Box::new(
+                        Response::builder()
+                            .status(StatusCode::UNAUTHORIZED)
+                            .header("WWW-Authenticate", "Bearer")
+                            .body(Body::empty())
+                            .unwrap(),
+                    )),
+                }
+            }
+            .boxed()
+        })
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn prefix_is_stripped() {
+    let server = IpfsWebDavBuilder::new(InMemoryApi::new())
+        .strip_prefix("/dav/")
+        .build()
+        .unwrap();
+    let res = request(&server, "PUT", "/dav/f.txt", &[], "f").await;
+    assert!(res.status.is_success(), "{}", res.status);
+    assert_eq!(get(&server, "/dav/f.txt").await.body, "f");
+
+    let res = propfind(&server, "/dav/", "1").await;
+    assert_eq!(res.status, StatusCode::MULTI_STATUS);
+    assert!(
+        res.body.contains("<D:href>/dav/f.txt</D:href>"),
+        "{}",
+        res.body
+    );
+}
+
+#[tokio::test]
+async fn read_only_share_allows_reads_only() {
+    let api = InMemoryApi::new();
+    api.write("/f.txt", 0, false, Bytes::from("f"))
+        .await
+        .unwrap();
+    let server = IpfsWebDavBuilder::new(api)
+        .locksystem(FakeLs::new())
+        .read_only(true)
+        .build()
+        .unwrap();
+
+    assert_eq!(get(&server, "/f.txt").await.body, "f");
+    assert_eq!(
+        propfind(&server, "/", "1").await.status,
+        StatusCode::MULTI_STATUS
+    );
+    for method in ["PUT", "DELETE", "MKCOL", "PROPPATCH"] {
+        let res = request(&server, method, "/f.txt", &[], "").await;
+        assert_eq!(res.status, StatusCode::METHOD_NOT_ALLOWED, "{}", method);
+    }
+    let res = request(&server, "OPTIONS", "/", &[], "").await;
+    assert!(
+        !res.header("allow").contains("PUT"),
+        "{}",
+        res.header("allow")
+    );
+}
+
+#[tokio::test]
+async fn auth_hook_guards_the_share() {
+    let server = authenticated_server();
+    assert_eq!(
+        handle(&server, "PUT", "/f.txt", None).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        handle(&server, "PUT", "/f.txt", Some("Bearer wrong")).await,
+        StatusCode::UNAUTHORIZED
+    );
+    let status = handle(&server, "PUT", "/f.txt", Some("Bearer secret")).await;
+    assert!(status.is_success(), "{}", status);
+    let res = request(
+        &server,
+        "GET",
+        "/f.txt",
+        &[("Authorization", "Bearer secret")],
+        "",
+    )
+    .await;
+    assert_eq!(res.body, "x");
+}
+
+#[tokio::test]
+async fn share_is_jailed_to_its_root() {
+    let api: Arc<InMemoryApi> = InMemoryApi::new().into();
+    api.write("/secret.txt", 0, false, Bytes::from("s"))
+        .await
+        .unwrap();
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .root("/shares/team-a/")
+        .build()
+        .unwrap();
+
+    mkcol(&server, "/docs/").await;
+    put(&server, "/docs/f.txt", "f").await;
+    let stored = api.read("/shares/team-a/docs/f.txt", 0, 1).await.unwrap();
+    assert_eq!(stored, "f");
+
+    let res = propfind(&server, "/", "1").await;
+    assert_eq!(res.status, StatusCode::MULTI_STATUS);
+    assert!(res.body.contains("<D:href>/docs/</D:href>"), "{}", res.body);
+    assert!(!res.body.contains("team-a"), "{}", res.body);
+    assert!(!res.body.contains("secret"), "{}", res.body);
+
+    for path in [
+        "/../secret.txt",
+        "/%2e%2e/secret.txt",
+        "/docs/%2E%2E/%2e%2e/secret.txt",
+    ] {
+        let res = get(&server, path).await;
+        assert!(!res.status.is_success(), "{}: {}", path, res.status);
+        assert_ne!(res.body, "s", "{}", path);
+    }
+    let headers = [("Destination", "/%2e%2e/stolen.txt")];
+    let res = request(&server, "COPY", "/docs/f.txt", &headers, "").await;
+    assert!(!res.status.is_success(), "{}", res.status);
+    assert!(matches!(
+        api.stat("/stolen.txt").await,
+        Err(PeerError::NotFound)
+    ));
+
+    let res = request(&server, "DELETE", "/", &[], "").await;
+    assert!(!res.status.is_success(), "{}", res.status);
+    assert!(api.stat("/shares/team-a").await.is_ok());
+}
+
+#[tokio::test]
+async fn read_only_share_leaves_mfs_alone() {
+    let api = RecordingApi::new();
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .root("/shares/a")
+        .write_policy(WritePolicy {
+            atomic: true,
+            ..Default::default()
+        })
+        .read_only(true)
+        .build()
+        .unwrap();
+
+    let res = propfind(&server, "/", "1").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        api.count(&["mkdir", "mkdir_with", "write", "write_from"]),
+        0
+    );
+}
+
+#[tokio::test]
+async fn read_only_collections_are_paths_of_the_share() {
+    let api: Arc<InMemoryApi> = InMemoryApi::new().into();
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .root("/shares/a")
+        .read_only_collection("/curated")
+        .build()
+        .unwrap();
+    mkcol(&server, "/scratch/").await;
+
+    let res = request(&server, "MKCOL", "/curated/", &[], "").await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    let res = request(&server, "PUT", "/curated/f.txt", &[], "f").await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    let res = request(&server, "PUT", "/scratch/f.txt", &[], "f").await;
+    assert!(res.status.is_success(), "{}", res.status);
+    assert!(api.stat("/shares/a/curated").await.is_err());
+}
+
+#[test]
+fn root_must_not_leave_mfs_paths() {
+    let res = IpfsWebDavBuilder::new(InMemoryApi::new())
+        .root("/shares/../etc")
+        .build();
+    assert!(matches!(res, Err(BuildError::InvalidPath(_))));
+}
+
+#[tokio::test]
+async fn read_only_collections_refuse_changes() {
+    let api: Arc<InMemoryApi> = InMemoryApi::new().into();
+    api.mkdir("/curated").await.unwrap();
+    api.write("/curated/f.txt", 0, false, Bytes::from("f"))
+        .await
+        .unwrap();
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .read_only_collection("/curated/")
+        .build()
+        .unwrap();
+    mkcol(&server, "/scratch/").await;
+    put(&server, "/scratch/g.txt", "g").await;
+
+    let forbidden = [
+        ("PUT", "/curated/f.txt", None),
+        ("PUT", "/curated/new.txt", None),
+        ("MKCOL", "/curated/sub/", None),
+        ("DELETE", "/curated/f.txt", None),
+        ("MOVE", "/curated/f.txt", Some("/scratch/f.txt")),
+        ("MOVE", "/scratch/g.txt", Some("/curated/g.txt")),
+        ("COPY", "/scratch/g.txt", Some("/curated/g.txt")),
+    ];
+    for (method, path, dest) in forbidden {
+        let headers: Vec<(&str, &str)> = dest.iter().map(|d| ("Destination", *d)).collect();
+        let res = request(&server, method, path, &headers, "").await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN, "{} {}", method, path);
+    }
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:z"><D:set><D:prop><Z:tag>x</Z:tag></D:prop></D:set></D:propertyupdate>"#;
+    let res = request(&server, "PROPPATCH", "/curated/f.txt", &[], body).await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN, "{}", res.body);
+
+    // the members that could not be deleted are reported in a multistatus
+    let res = request(&server, "DELETE", "/curated/", &[], "").await;
+    assert_eq!(res.status, StatusCode::MULTI_STATUS);
+    assert!(res.body.contains("403"), "{}", res.body);
+
+    let headers = [("Destination", "/scratch/f.txt")];
+    let res = request(&server, "COPY", "/curated/f.txt", &headers, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(get(&server, "/scratch/f.txt").await.body, "f");
+    assert_eq!(get(&server, "/curated/f.txt").await.body, "f");
+    assert_eq!(api.ls("/curated").await.unwrap().len(), 1);
+}
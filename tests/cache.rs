@@ -0,0 +1,206 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use common::{get, mkcol, propfind, put, request};
+use http::StatusCode;
+use ipfs_webdav::api::{InMemoryApi, PeerApi};
+use ipfs_webdav::{CachePolicy, IpfsWebDav, IpfsWebDavBuilder};
+
+fn shared_server(policy: CachePolicy) -> (Arc<InMemoryApi>, IpfsWebDav) {
+    let api: Arc<InMemoryApi> = InMemoryApi::new().into();
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .cache_policy(policy)
+        .build()
+        .unwrap();
+    (api, server)
+}
+
+#[tokio::test]
+async fn expired_entries_are_fetched_again() {
+    let (api, server) = shared_server(CachePolicy {
+        ttl: Some(Duration::ZERO),
+        revalidate: None,
+        ..Default::default()
+    });
+    put(&server, "/f.txt", "data").await;
+    put(&server, "/g.txt", "data").await;
+    assert_eq!(get(&server, "/f.txt").await.header("content-length"), "4");
+
+    api.write("/f.txt", 0, true, Bytes::from("changed"))
+        .await
+        .unwrap();
+    api.rm("/g.txt").await.unwrap();
+    api.mkdir("/new").await.unwrap();
+
+    let res = get(&server, "/f.txt").await;
+    assert_eq!(res.header("content-length"), "7");
+    assert_eq!(res.body, "changed");
+    assert_eq!(get(&server, "/g.txt").await.status, StatusCode::NOT_FOUND);
+
+    let res = propfind(&server, "/", "1").await;
+    assert!(res.body.contains("<D:href>/new/</D:href>"), "{}", res.body);
+    assert!(!res.body.contains("g.txt"), "{}", res.body);
+}
+
+#[tokio::test]
+async fn root_cid_change_drops_cache() {
+    let (api, server) = shared_server(CachePolicy {
+        ttl: None,
+        revalidate: Some(Duration::ZERO),
+        ..Default::default()
+    });
+    put(&server, "/f.txt", "data").await;
+    assert_eq!(get(&server, "/f.txt").await.body, "data");
+
+    api.write("/f.txt", 0, true, Bytes::from("changed"))
+        .await
+        .unwrap();
+    let res = get(&server, "/f.txt").await;
+    assert_eq!(res.header("content-length"), "7");
+
+    api.rm("/f.txt").await.unwrap();
+    assert_eq!(get(&server, "/f.txt").await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn without_expiry_the_cache_is_trusted() {
+    let (api, server) = shared_server(CachePolicy {
+        ttl: None,
+        revalidate: None,
+        ..Default::default()
+    });
+    put(&server, "/f.txt", "data").await;
+    api.write("/f.txt", 0, true, Bytes::from("changed"))
+        .await
+        .unwrap();
+    let res = propfind(&server, "/f.txt", "0").await;
+    assert!(
+        res.body
+            .contains("<D:getcontentlength>4</D:getcontentlength>"),
+        "{}",
+        res.body
+    );
+}
+
+#[tokio::test]
+async fn shrunk_file_is_read_to_its_end() {
+    let (api, server) = shared_server(CachePolicy {
+        ttl: None,
+        revalidate: None,
+        ..Default::default()
+    });
+    put(&server, "/f.txt", "0123456789").await;
+    api.write("/f.txt", 0, true, Bytes::from("abc"))
+        .await
+        .unwrap();
+
+    let res = get(&server, "/f.txt").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.header("content-length"), "3");
+    assert_eq!(res.body, "abc");
+}
+
+#[tokio::test]
+async fn move_and_copy_rewrite_only_the_prefix() {
+    let (_, server) = shared_server(CachePolicy {
+        ttl: None,
+        revalidate: None,
+        ..Default::default()
+    });
+    for dir in ["/a/", "/a/b/", "/a/b/a/", "/a/b/a/b/", "/ab/"] {
+        mkcol(&server, dir).await;
+    }
+    put(&server, "/a/b/a/b/f.txt", "deep").await;
+    put(&server, "/ab/g.txt", "sibling").await;
+
+    let dest = [("Destination", "http://localhost/c/")];
+    let res = request(&server, "COPY", "/a/b/", &dest, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(get(&server, "/c/a/b/f.txt").await.body, "deep");
+
+    let dest = [("Destination", "http://localhost/z/")];
+    let res = request(&server, "MOVE", "/a/", &dest, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(get(&server, "/z/b/a/b/f.txt").await.body, "deep");
+    assert_eq!(
+        get(&server, "/a/b/a/b/f.txt").await.status,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(get(&server, "/ab/g.txt").await.body, "sibling");
+    assert_eq!(get(&server, "/c/a/b/f.txt").await.body, "deep");
+}
+
+#[tokio::test]
+async fn collections_are_created_below_uncached_parents() {
+    let (api, server) = shared_server(CachePolicy {
+        ttl: None,
+        revalidate: Some(Duration::ZERO),
+        max_entries: Some(2),
+        ..Default::default()
+    });
+    mkcol(&server, "/d/").await;
+    for name in ["a", "b", "c"] {
+        put(&server, &format!("/{}.txt", name), name).await;
+    }
+    mkcol(&server, "/d/evicted/").await;
+
+    // a change behind the server's back drops the whole cache
+    api.mkdir("/other").await.unwrap();
+    propfind(&server, "/", "0").await;
+    mkcol(&server, "/d/revalidated/").await;
+
+    let res = request(&server, "MKCOL", "/missing/sub/", &[], "").await;
+    assert_eq!(res.status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn cache_is_bounded() {
+    let builder = IpfsWebDavBuilder::new(InMemoryApi::new()).cache_policy(CachePolicy {
+        max_entries: Some(3),
+        ..Default::default()
+    });
+    let stats = builder.cache_stats();
+    let server = builder.build().unwrap();
+
+    put(&server, "/a.txt", "a").await;
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="http://example.com/ns">
+  <D:set><D:prop><Z:color>blue</Z:color></D:prop></D:set>
+</D:propertyupdate>"#;
+    request(&server, "PROPPATCH", "/a.txt", &[], body).await;
+    for name in ["b", "c", "d", "e"] {
+        put(&server, &format!("/{}.txt", name), name).await;
+    }
+    let res = propfind(&server, "/", "1").await;
+    assert_eq!(res.body.matches("<D:response>").count(), 6, "{}", res.body);
+    assert!(stats.entries() <= 3, "{}", stats.entries());
+    assert!(stats.evictions() > 0);
+
+    let misses = stats.misses();
+    assert_eq!(get(&server, "/a.txt").await.body, "a");
+    assert!(stats.misses() > misses);
+    assert!(stats.hits() > 0);
+    assert!(propfind(&server, "/a.txt", "0").await.body.contains("blue"));
+
+    let builder = IpfsWebDavBuilder::new(InMemoryApi::new()).cache_policy(CachePolicy {
+        max_bytes: Some(1),
+        ..Default::default()
+    });
+    let stats = builder.cache_stats();
+    let server = builder.build().unwrap();
+    put(&server, "/a.txt", "a").await;
+    put(&server, "/b.txt", "b").await;
+    assert_eq!(stats.entries(), 1);
+    assert_eq!(get(&server, "/a.txt").await.body, "a");
+}
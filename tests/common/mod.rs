@@ -0,0 +1,305 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+#![allow(dead_code)]
+
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use http::{HeaderMap, Request, StatusCode};
+use ipfs_webdav::api::{
+    DagOptions, InMemoryApi, PeerApi, PeerBody, PeerEntry, PeerError, PeerKey, PeerStream,
+};
+use ipfs_webdav::IpfsWebDav;
+
+/// Response of a WebDAV request with the body collected into a string.
+pub struct DavResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl DavResponse {
+    pub fn header(&self, name: &str) -> &str {
+        self.headers
+            .get(name)
+            .map(|v| v.to_str().unwrap())
+            .unwrap_or_default()
+    }
+}
+
+/// Creates a WebDAV handler on top of an empty in-memory MFS.
+pub fn server() -> IpfsWebDav {
+    ipfs_webdav::make_server(InMemoryApi::new())
+}
+
+/// Issues a request against the handler and collects the response.
+pub async fn request(
+    server: &IpfsWebDav,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> DavResponse {
+    let mut req = Request::builder().method(method).uri(path);
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let req = req.body(hyper::Body::from(body.to_string())).unwrap();
+    let res = server.handle(req).await;
+    let status = res.status();
+    let headers = res.headers().clone();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    DavResponse {
+        status,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    }
+}
+
+/// Uploads a file with PUT and asserts that it succeeded.
+pub async fn put(server: &IpfsWebDav, path: &str, body: &str) {
+    let res = request(server, "PUT", path, &[], body).await;
+    assert!(res.status.is_success(), "PUT {}: {}", path, res.status);
+}
+
+/// Creates a collection with MKCOL and asserts that it succeeded.
+pub async fn mkcol(server: &IpfsWebDav, path: &str) {
+    let res = request(server, "MKCOL", path, &[], "").await;
+    assert_eq!(res.status, StatusCode::CREATED, "MKCOL {}", path);
+}
+
+/// Fetches a file with GET and returns its body.
+pub async fn get(server: &IpfsWebDav, path: &str) -> DavResponse {
+    request(server, "GET", path, &[], "").await
+}
+
+/// Runs a PROPFIND with the given depth and an allprop body.
+pub async fn propfind(server: &IpfsWebDav, path: &str, depth: &str) -> DavResponse {
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind>"#;
+    request(server, "PROPFIND", path, &[("Depth", depth)], body).await
+}
+
+/// `InMemoryApi` that records the calls it receives, along with the path and the
+/// DAG options they were given.
+#[derive(Debug)]
+pub struct RecordingApi {
+    inner: Box<InMemoryApi>,
+    calls: Mutex<Vec<(&'static str, String, Option<DagOptions>)>>,
+    failing: Mutex<Vec<&'static str>>,
+    chunk: Option<usize>,
+}
+
+impl RecordingApi {
+    pub fn new() -> Arc<RecordingApi> {
+        RecordingApi::with_chunks(None)
+    }
+
+    /// Streams files in chunks of the given size instead of all at once.
+    pub fn streaming(chunk: usize) -> Arc<RecordingApi> {
+        RecordingApi::with_chunks(Some(chunk))
+    }
+
+    fn with_chunks(chunk: Option<usize>) -> Arc<RecordingApi> {
+        Arc::new(RecordingApi {
+            inner: InMemoryApi::new(),
+            calls: Mutex::new(Vec::new()),
+            failing: Mutex::new(Vec::new()),
+            chunk,
+        })
+    }
+
+    /// The API the calls are forwarded to, for setups that must not be recorded.
+    pub fn inner(&self) -> &InMemoryApi {
+        &self.inner
+    }
+
+    /// Number of calls to any of the given methods.
+    pub fn count(&self, methods: &[&str]) -> usize {
+        let calls = self.calls.lock().unwrap();
+        calls.iter().filter(|c| methods.contains(&c.0)).count()
+    }
+
+    /// Paths the given method was called with, in order.
+    pub fn paths(&self, method: &str) -> Vec<String> {
+        let calls = self.calls.lock().unwrap();
+        calls
+            .iter()
+            .filter(|c| c.0 == method)
+            .map(|c| c.1.clone())
+            .collect()
+    }
+
+    /// Calls that were given DAG options, in order.
+    pub fn dags(&self) -> Vec<(&'static str, String, DagOptions)> {
+        let calls = self.calls.lock().unwrap();
+        calls
+            .iter()
+            .filter_map(|(m, p, o)| o.clone().map(|o| (*m, p.clone(), o)))
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.calls.lock().unwrap().clear();
+    }
+
+    /// Makes the calls to a method fail as if the node could not be reached.
+    pub fn fail(&self, method: &'static str) {
+        self.failing.lock().unwrap().push(method);
+    }
+
+    fn record(
+        &self,
+        method: &'static str,
+        path: &str,
+        options: Option<&DagOptions>,
+    ) -> Result<(), PeerError> {
+        let call = (method, path.to_string(), options.cloned());
+        self.calls.lock().unwrap().push(call);
+        match self.failing.lock().unwrap().contains(&method) {
+            true => Err(PeerError::Unavailable),
+            false => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl PeerApi for RecordingApi {
+    async fn cp(&self, path: &str, dest: &str) -> Result<(), PeerError> {
+        self.record("cp", path, None)?;
+        self.inner.cp(path, dest).await
+    }
+
+    async fn flush(&self, path: &str) -> Result<(), PeerError> {
+        self.record("flush", path, None)?;
+        self.inner.flush(path).await
+    }
+
+    async fn ls(&self, path: &str) -> Result<Vec<PeerEntry>, PeerError> {
+        self.record("ls", path, None)?;
+        self.inner.ls(path).await
+    }
+
+    async fn mkdir(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        self.record("mkdir", path, None)?;
+        self.inner.mkdir(path).await
+    }
+
+    async fn mv(&self, path: &str, dest: &str) -> Result<(), PeerError> {
+        self.record("mv", path, None)?;
+        self.inner.mv(path, dest).await
+    }
+
+    async fn read(&self, path: &str, offset: usize, count: usize) -> Result<Bytes, PeerError> {
+        self.record("read", path, None)?;
+        self.inner.read(path, offset, count).await
+    }
+
+    async fn read_stream(&self, path: &str, offset: usize) -> Result<PeerStream, PeerError> {
+        self.record("read_stream", path, None)?;
+        let chunk = match self.chunk {
+            Some(chunk) => chunk,
+            None => return self.inner.read_stream(path, offset).await,
+        };
+        let data = self.inner.read(path, offset, usize::MAX).await?;
+        let chunks: Vec<Result<Bytes, PeerError>> = (0..data.len())
+            .step_by(chunk)
+            .map(|i| Ok(data.slice(i..data.len().min(i + chunk))))
+            .collect();
+        Ok(stream::iter(chunks).boxed())
+    }
+
+    async fn rm(&self, path: &str) -> Result<(), PeerError> {
+        self.record("rm", path, None)?;
+        self.inner.rm(path).await
+    }
+
+    async fn stat(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        self.record("stat", path, None)?;
+        self.inner.stat(path).await
+    }
+
+    async fn write(
+        &self,
+        path: &str,
+        offset: usize,
+        truncate: bool,
+        data: Bytes,
+    ) -> Result<(), PeerError> {
+        self.record("write", path, None)?;
+        self.inner.write(path, offset, truncate, data).await
+    }
+
+    async fn write_from(
+        &self,
+        path: &str,
+        offset: usize,
+        truncate: bool,
+        data: PeerBody,
+        options: &DagOptions,
+    ) -> Result<(), PeerError> {
+        self.record("write_from", path, Some(options))?;
+        self.inner
+            .write_from(path, offset, truncate, data, options)
+            .await
+    }
+
+    async fn add(&self, path: &str, data: PeerBody, options: &DagOptions) -> Result<(), PeerError> {
+        self.record("add", path, Some(options))?;
+        self.inner.add(path, data, options).await
+    }
+
+    async fn mkdir_with(&self, path: &str, options: &DagOptions) -> Result<PeerEntry, PeerError> {
+        self.record("mkdir_with", path, Some(options))?;
+        self.inner.mkdir_with(path, options).await
+    }
+
+    async fn chcid(&self, path: &str, options: &DagOptions) -> Result<(), PeerError> {
+        self.record("chcid", path, Some(options))?;
+        self.inner.chcid(path, options).await
+    }
+
+    async fn touch(&self, path: &str, mtime: SystemTime) -> Result<(), PeerError> {
+        self.record("touch", path, None)?;
+        self.inner.touch(path, mtime).await
+    }
+
+    async fn ipfs_ls(&self, path: &str) -> Result<Vec<PeerEntry>, PeerError> {
+        self.record("ipfs_ls", path, None)?;
+        self.inner.ipfs_ls(path).await
+    }
+
+    async fn ipfs_stat(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        self.record("ipfs_stat", path, None)?;
+        self.inner.ipfs_stat(path).await
+    }
+
+    async fn cat(&self, path: &str, offset: usize) -> Result<PeerStream, PeerError> {
+        self.record("cat", path, None)?;
+        self.inner.cat(path, offset).await
+    }
+
+    async fn resolve_name(&self, name: &str) -> Result<String, PeerError> {
+        self.record("resolve_name", name, None)?;
+        self.inner.resolve_name(name).await
+    }
+
+    async fn key_list(&self) -> Result<Vec<PeerKey>, PeerError> {
+        self.record("key_list", "", None)?;
+        self.inner.key_list().await
+    }
+
+    async fn name_publish(&self, path: &str, key: &str) -> Result<String, PeerError> {
+        self.record("name_publish", path, None)?;
+        self.inner.name_publish(path, key).await
+    }
+}
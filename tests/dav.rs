@@ -0,0 +1,541 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::Bytes;
+use common::{get, mkcol, propfind, put, request, server, RecordingApi};
+use http::StatusCode;
+use ipfs_webdav::api::{InMemoryApi, PeerApi};
+use ipfs_webdav::{FlushPolicy, IpfsWebDavBuilder};
+
+#[tokio::test]
+async fn put_and_get() {
+    let server = server();
+    put(&server, "/hello.txt", "hello world").await;
+
+    let res = get(&server, "/hello.txt").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, "hello world");
+    assert_eq!(res.header("content-length"), "11");
+
+    put(&server, "/hello.txt", "bye").await;
+    assert_eq!(get(&server, "/hello.txt").await.body, "bye");
+}
+
+#[tokio::test]
+async fn get_missing_is_not_found() {
+    let server = server();
+    assert_eq!(get(&server, "/missing").await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn get_with_range() {
+    let server = server();
+    put(&server, "/range.txt", "0123456789").await;
+
+    let res = request(&server, "GET", "/range.txt", &[("Range", "bytes=2-5")], "").await;
+    assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.body, "2345");
+    assert_eq!(res.header("content-range"), "bytes 2-5/10");
+
+    let res = request(&server, "GET", "/range.txt", &[("Range", "bytes=-3")], "").await;
+    assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.body, "789");
+
+    let res = request(&server, "GET", "/range.txt", &[("Range", "bytes=8-20")], "").await;
+    assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.body, "89");
+    assert_eq!(res.header("content-range"), "bytes 8-9/10");
+}
+
+#[tokio::test]
+async fn put_reports_written_file() {
+    let server = server();
+    let res = request(&server, "PUT", "/new.txt", &[], "new").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(
+        res.header("etag"),
+        get(&server, "/new.txt").await.header("etag")
+    );
+    assert!(!res.header("last-modified").is_empty());
+}
+
+#[tokio::test]
+async fn mkcol_and_propfind() {
+    let server = server();
+    mkcol(&server, "/dir/").await;
+    put(&server, "/dir/a.txt", "a").await;
+    put(&server, "/dir/b.txt", "bb").await;
+
+    let res = request(&server, "MKCOL", "/dir/", &[], "").await;
+    assert_eq!(res.status, StatusCode::METHOD_NOT_ALLOWED);
+    let res = request(&server, "MKCOL", "/none/dir/", &[], "").await;
+    assert_eq!(res.status, StatusCode::CONFLICT);
+
+    let res = propfind(&server, "/dir/", "1").await;
+    assert_eq!(res.status, StatusCode::MULTI_STATUS);
+    assert!(res.body.contains("<D:href>/dir/</D:href>"));
+    assert!(res.body.contains("<D:href>/dir/a.txt</D:href>"));
+    assert!(res.body.contains("<D:href>/dir/b.txt</D:href>"));
+    assert!(res
+        .body
+        .contains("<D:getcontentlength>2</D:getcontentlength>"));
+
+    let res = propfind(&server, "/dir/", "0").await;
+    assert!(!res.body.contains("a.txt"));
+}
+
+#[tokio::test]
+async fn copy_file_and_collection() {
+    let server = server();
+    mkcol(&server, "/src/").await;
+    put(&server, "/src/f.txt", "data").await;
+
+    let dest = [("Destination", "http://localhost/dst/")];
+    let res = request(&server, "COPY", "/src/", &dest, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(get(&server, "/dst/f.txt").await.body, "data");
+    assert_eq!(get(&server, "/src/f.txt").await.body, "data");
+
+    let dest = [
+        ("Destination", "http://localhost/dst/f.txt"),
+        ("Overwrite", "F"),
+    ];
+    let res = request(&server, "COPY", "/src/f.txt", &dest, "").await;
+    assert_eq!(res.status, StatusCode::PRECONDITION_FAILED);
+}
+
+#[tokio::test]
+async fn move_file_and_collection() {
+    let server = server();
+    mkcol(&server, "/a/").await;
+    put(&server, "/a/f.txt", "data").await;
+
+    let dest = [("Destination", "http://localhost/b/")];
+    let res = request(&server, "MOVE", "/a/", &dest, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(get(&server, "/a/f.txt").await.status, StatusCode::NOT_FOUND);
+    assert_eq!(get(&server, "/b/f.txt").await.body, "data");
+
+    let dest = [("Destination", "http://localhost/g.txt")];
+    let res = request(&server, "MOVE", "/b/f.txt", &dest, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(get(&server, "/g.txt").await.body, "data");
+}
+
+#[tokio::test]
+async fn delete_file_and_collection() {
+    let server = server();
+    mkcol(&server, "/d/").await;
+    put(&server, "/d/f.txt", "data").await;
+    put(&server, "/g.txt", "data").await;
+
+    let res = request(&server, "DELETE", "/g.txt", &[], "").await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    let res = request(&server, "DELETE", "/d/", &[], "").await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    assert_eq!(get(&server, "/d/f.txt").await.status, StatusCode::NOT_FOUND);
+
+    let res = request(&server, "DELETE", "/d/", &[], "").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn lock_and_unlock() {
+    let server = server();
+    put(&server, "/locked.txt", "data").await;
+
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:lockinfo xmlns:D="DAV:">
+  <D:lockscope><D:exclusive/></D:lockscope>
+  <D:locktype><D:write/></D:locktype>
+  <D:owner>test</D:owner>
+</D:lockinfo>"#;
+    let res = request(
+        &server,
+        "LOCK",
+        "/locked.txt",
+        &[("Timeout", "Second-60")],
+        body,
+    )
+    .await;
+    assert_eq!(res.status, StatusCode::OK);
+    let token = res.header("lock-token").to_string();
+    assert!(token.starts_with("<urn:uuid:"), "{}", token);
+
+    let res = request(&server, "PUT", "/locked.txt", &[], "other").await;
+    assert_eq!(res.status, StatusCode::LOCKED);
+
+    let res = request(
+        &server,
+        "UNLOCK",
+        "/locked.txt",
+        &[("Lock-Token", &token)],
+        "",
+    )
+    .await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    put(&server, "/locked.txt", "other").await;
+    assert_eq!(get(&server, "/locked.txt").await.body, "other");
+}
+
+#[tokio::test]
+async fn proppatch_and_propfind() {
+    let server = server();
+    put(&server, "/props.txt", "data").await;
+
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="http://example.com/ns">
+  <D:set><D:prop><Z:color>blue</Z:color></D:prop></D:set>
+</D:propertyupdate>"#;
+    let res = request(&server, "PROPPATCH", "/props.txt", &[], body).await;
+    assert_eq!(res.status, StatusCode::MULTI_STATUS);
+    assert!(res.body.contains("200 OK"));
+
+    let res = propfind(&server, "/props.txt", "0").await;
+    assert!(res.body.contains("blue"), "{}", res.body);
+
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="http://example.com/ns">
+  <D:remove><D:prop><Z:color/></D:prop></D:remove>
+</D:propertyupdate>"#;
+    let res = request(&server, "PROPPATCH", "/props.txt", &[], body).await;
+    assert_eq!(res.status, StatusCode::MULTI_STATUS);
+    let res = propfind(&server, "/props.txt", "0").await;
+    assert!(!res.body.contains("blue"));
+}
+
+#[tokio::test]
+async fn put_empty_file() {
+    let server = server();
+    put(&server, "/empty.txt", "").await;
+    let res = get(&server, "/empty.txt").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, "");
+}
+
+#[tokio::test]
+async fn propfind_reports_stored_mtime() {
+    let api = InMemoryApi::new();
+    api.write("/old.txt", 0, false, Bytes::from("data"))
+        .await
+        .unwrap();
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    api.touch("/old.txt", mtime).await.unwrap();
+    let server = ipfs_webdav::make_server(api);
+
+    let modified = "<D:getlastmodified>Sun, 09 Sep 2001 01:46:40 GMT</D:getlastmodified>";
+    for _ in 0..2 {
+        let res = propfind(&server, "/", "1").await;
+        assert!(res.body.contains(modified), "{}", res.body);
+    }
+
+    put(&server, "/old.txt", "new data").await;
+    let res = propfind(&server, "/old.txt", "0").await;
+    assert!(!res.body.contains(modified), "{}", res.body);
+}
+
+#[tokio::test]
+async fn propfind_reports_cid() {
+    let server = server();
+    mkcol(&server, "/dir/").await;
+    put(&server, "/dir/f.txt", "data").await;
+
+    let cid = |body: &str| {
+        let start = body
+            .find("<I:cid")
+            .map(|i| i + body[i..].find('>').unwrap() + 1);
+        let start = start.expect(body);
+        body[start..start + body[start..].find('<').unwrap()].to_string()
+    };
+    let file = cid(&propfind(&server, "/dir/f.txt", "0").await.body);
+    let dir = cid(&propfind(&server, "/dir/", "0").await.body);
+    assert!(!file.is_empty());
+    assert_ne!(file, dir);
+
+    put(&server, "/dir/f.txt", "other").await;
+    assert_ne!(cid(&propfind(&server, "/dir/f.txt", "0").await.body), file);
+    assert_ne!(cid(&propfind(&server, "/dir/", "0").await.body), dir);
+
+    put(&server, "/dir/f.txt", "data").await;
+    assert_eq!(cid(&propfind(&server, "/dir/f.txt", "0").await.body), file);
+}
+
+#[tokio::test]
+async fn cid_is_protected() {
+    let server = server();
+    put(&server, "/f.txt", "data").await;
+
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:I="https://ipfs.tech/ns" xmlns:Z="http://example.com/ns">
+  <D:set><D:prop><I:cid>bogus</I:cid><Z:color>blue</Z:color></D:prop></D:set>
+</D:propertyupdate>"#;
+    let res = request(&server, "PROPPATCH", "/f.txt", &[], body).await;
+    assert_eq!(res.status, StatusCode::MULTI_STATUS);
+    assert!(res.body.contains("403 Forbidden"), "{}", res.body);
+    assert!(res.body.contains("424 Failed Dependency"), "{}", res.body);
+
+    let res = propfind(&server, "/f.txt", "0").await;
+    assert!(!res.body.contains("bogus"), "{}", res.body);
+    assert!(!res.body.contains("blue"), "{}", res.body);
+}
+
+#[tokio::test]
+async fn etag_follows_content() {
+    let server = server();
+    put(&server, "/e.txt", "data").await;
+
+    let etag = get(&server, "/e.txt").await.header("etag").to_string();
+    assert!(etag.starts_with('"'), "{}", etag);
+    assert_eq!(get(&server, "/e.txt").await.header("etag"), etag);
+
+    let res = request(&server, "GET", "/e.txt", &[("If-None-Match", &etag)], "").await;
+    assert_eq!(res.status, StatusCode::NOT_MODIFIED);
+
+    put(&server, "/e.txt", "other").await;
+    let res = request(&server, "GET", "/e.txt", &[("If-None-Match", &etag)], "").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_ne!(res.header("etag"), etag);
+
+    let res = request(&server, "PUT", "/e.txt", &[("If-Match", &etag)], "lost").await;
+    assert_eq!(res.status, StatusCode::PRECONDITION_FAILED);
+    let current = get(&server, "/e.txt").await.header("etag").to_string();
+    let res = request(&server, "PUT", "/e.txt", &[("If-Match", &current)], "kept").await;
+    assert!(res.status.is_success(), "{}", res.status);
+    assert_eq!(get(&server, "/e.txt").await.body, "kept");
+}
+
+#[tokio::test]
+async fn properties_are_persisted() {
+    let api: Arc<InMemoryApi> = InMemoryApi::new().into();
+    let server = ipfs_webdav::make_server(Box::new(api.clone()));
+    mkcol(&server, "/d/").await;
+    put(&server, "/d/f.txt", "data").await;
+
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="http://example.com/ns">
+  <D:set><D:prop><Z:color>blue</Z:color></D:prop></D:set>
+</D:propertyupdate>"#;
+    let res = request(&server, "PROPPATCH", "/d/f.txt", &[], body).await;
+    assert!(res.body.contains("200 OK"), "{}", res.body);
+
+    // a new handler knows only what MFS holds
+    let server = ipfs_webdav::make_server(Box::new(api.clone()));
+    let res = propfind(&server, "/d/f.txt", "0").await;
+    assert!(res.body.contains("blue"), "{}", res.body);
+
+    let dest = [("Destination", "http://localhost/e/")];
+    let res = request(&server, "MOVE", "/d/", &dest, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    let dest = [("Destination", "http://localhost/g.txt")];
+    let res = request(&server, "COPY", "/e/f.txt", &dest, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+
+    let server = ipfs_webdav::make_server(Box::new(api));
+    assert!(propfind(&server, "/e/f.txt", "0")
+        .await
+        .body
+        .contains("blue"));
+    assert!(propfind(&server, "/g.txt", "0").await.body.contains("blue"));
+
+    let res = request(&server, "DELETE", "/g.txt", &[], "").await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    put(&server, "/g.txt", "new").await;
+    assert!(!propfind(&server, "/g.txt", "0").await.body.contains("blue"));
+}
+
+#[tokio::test]
+async fn property_store_is_hidden() {
+    let server = server();
+    put(&server, "/f.txt", "data").await;
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="http://example.com/ns">
+  <D:set><D:prop><Z:color>blue</Z:color></D:prop></D:set>
+</D:propertyupdate>"#;
+    request(&server, "PROPPATCH", "/f.txt", &[], body).await;
+
+    let res = propfind(&server, "/", "1").await;
+    assert!(!res.body.contains(".ipfs-webdav"), "{}", res.body);
+    let res = propfind(&server, "/.ipfs-webdav/", "0").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = request(&server, "PUT", "/.ipfs-webdav/props/x", &[], "x").await;
+    assert!(res.status.is_client_error(), "{}", res.status);
+}
+
+#[tokio::test]
+async fn listing_keeps_cached_properties() {
+    let api: Arc<InMemoryApi> = InMemoryApi::new().into();
+    let server = ipfs_webdav::make_server(Box::new(api.clone()));
+    mkcol(&server, "/d/").await;
+    put(&server, "/d/f.txt", "data").await;
+
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="http://example.com/ns">
+  <D:set><D:prop><Z:color>blue</Z:color></D:prop></D:set>
+</D:propertyupdate>"#;
+    request(&server, "PROPPATCH", "/d/f.txt", &[], body).await;
+    request(&server, "PROPPATCH", "/d/", &[], body).await;
+
+    // only the cache can still answer once the stored copy is gone
+    api.rm("/.ipfs-webdav").await.unwrap();
+    for _ in 0..2 {
+        let res = propfind(&server, "/d/", "1").await;
+        assert_eq!(res.body.matches("blue").count(), 2, "{}", res.body);
+        let res = propfind(&server, "/", "1").await;
+        assert!(res.body.contains("blue"), "{}", res.body);
+    }
+    put(&server, "/d/f.txt", "new data").await;
+    assert!(propfind(&server, "/d/f.txt", "0")
+        .await
+        .body
+        .contains("blue"));
+}
+
+#[tokio::test]
+async fn listing_loads_properties_together() {
+    let api = RecordingApi::new();
+    let server = ipfs_webdav::make_server(Box::new(api.clone()));
+    mkcol(&server, "/d/").await;
+    for name in ["a", "b", "c", "d", "e"] {
+        put(&server, &format!("/d/{}.txt", name), name).await;
+    }
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="http://example.com/ns">
+  <D:set><D:prop><Z:color>blue</Z:color></D:prop></D:set>
+</D:propertyupdate>"#;
+    request(&server, "PROPPATCH", "/d/a.txt", &[], body).await;
+    request(&server, "PROPPATCH", "/d/b.txt", &[], body).await;
+
+    let server = ipfs_webdav::make_server(Box::new(api.clone()));
+    api.clear();
+    let res = propfind(&server, "/d/", "1").await;
+    assert_eq!(res.body.matches("blue").count(), 2, "{}", res.body);
+    // the two patched files and the collection itself
+    assert_eq!(api.count(&["read"]), 3);
+}
+
+#[tokio::test]
+async fn collection_copy_keeps_its_properties() {
+    let api: Arc<InMemoryApi> = InMemoryApi::new().into();
+    let server = ipfs_webdav::make_server(Box::new(api.clone()));
+    mkcol(&server, "/d/").await;
+    mkcol(&server, "/d/sub/").await;
+    put(&server, "/d/sub/f.txt", "data").await;
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="http://example.com/ns">
+  <D:set><D:prop><Z:color>blue</Z:color></D:prop></D:set>
+</D:propertyupdate>"#;
+    request(&server, "PROPPATCH", "/d/", &[], body).await;
+    request(&server, "PROPPATCH", "/d/sub/", &[], body).await;
+
+    let dest = [("Destination", "http://localhost/e/")];
+    let res = request(&server, "COPY", "/d/", &dest, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    let dest = [("Destination", "http://localhost/z/"), ("Depth", "0")];
+    let res = request(&server, "COPY", "/d/", &dest, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    let res = request(&server, "MKCOL", "/other/", &[], "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    propfind(&server, "/d/", "1").await;
+
+    let server = ipfs_webdav::make_server(Box::new(api));
+    for dir in ["/e/", "/e/sub/", "/z/"] {
+        assert!(
+            propfind(&server, dir, "0").await.body.contains("blue"),
+            "{}",
+            dir
+        );
+    }
+    assert!(!propfind(&server, "/other/", "0")
+        .await
+        .body
+        .contains("blue"));
+}
+
+#[tokio::test]
+async fn unpatched_paths_have_no_sidecar() {
+    let api = RecordingApi::new();
+    let server = ipfs_webdav::make_server(Box::new(api.clone()));
+    mkcol(&server, "/d/").await;
+    put(&server, "/d/f.txt", "data").await;
+
+    let dest = [("Destination", "http://localhost/e/")];
+    let res = request(&server, "COPY", "/d/", &dest, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    let dest = [("Destination", "http://localhost/g/")];
+    let res = request(&server, "MOVE", "/d/", &dest, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+
+    let mkdirs = api.paths("mkdir");
+    assert!(
+        !mkdirs.iter().any(|p| p.starts_with("/.ipfs-webdav")),
+        "{:?}",
+        mkdirs
+    );
+    assert!(api.inner().ls("/.ipfs-webdav/props").await.is_err());
+}
+
+#[tokio::test]
+async fn properties_are_flushed_by_policy() {
+    let api = RecordingApi::new();
+    let builder = IpfsWebDavBuilder::new(Box::new(api.clone())).flush_policy(FlushPolicy::Manual);
+    let flusher = builder.flusher();
+    let server = builder.build().unwrap();
+    mkcol(&server, "/d/").await;
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="http://example.com/ns">
+  <D:set><D:prop><Z:color>blue</Z:color></D:prop></D:set>
+</D:propertyupdate>"#;
+    let res = request(&server, "PROPPATCH", "/d/", &[], body).await;
+    assert!(res.body.contains("200 OK"), "{}", res.body);
+    assert_eq!(api.count(&["flush"]), 0);
+    assert!(flusher.is_pending());
+}
+
+#[tokio::test]
+async fn partial_put_and_patch() {
+    let server = server();
+    put(&server, "/part.bin", "0123456789").await;
+
+    let headers = [("Content-Range", "bytes 10-14/*")];
+    let res = request(&server, "PUT", "/part.bin", &headers, "abcde").await;
+    assert!(res.status.is_success(), "{}", res.status);
+    assert_eq!(get(&server, "/part.bin").await.body, "0123456789abcde");
+
+    let headers = [("Content-Range", "bytes 2-3/*")];
+    let res = request(&server, "PUT", "/part.bin", &headers, "XY").await;
+    assert!(res.status.is_success(), "{}", res.status);
+    assert_eq!(get(&server, "/part.bin").await.body, "01XY456789abcde");
+
+    let sabre = ("Content-Type", "application/x-sabredav-partialupdate");
+    let headers = [sabre, ("Content-Length", "2"), ("X-Update-Range", "append")];
+    let res = request(&server, "PATCH", "/part.bin", &headers, "++").await;
+    assert!(res.status.is_success(), "{}", res.status);
+    let headers = [
+        sabre,
+        ("Content-Length", "2"),
+        ("X-Update-Range", "bytes=0-1"),
+    ];
+    let res = request(&server, "PATCH", "/part.bin", &headers, "ab").await;
+    assert!(res.status.is_success(), "{}", res.status);
+    let headers = [
+        sabre,
+        ("Content-Length", "2"),
+        ("X-Update-Range", "bytes=-2"),
+    ];
+    let res = request(&server, "PATCH", "/part.bin", &headers, "!!").await;
+    assert!(res.status.is_success(), "{}", res.status);
+
+    let res = get(&server, "/part.bin").await;
+    assert_eq!(res.body, "abXY456789abcde!!");
+    assert_eq!(res.header("content-length"), "17");
+}
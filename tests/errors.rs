@@ -0,0 +1,186 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{Request, StatusCode};
+use ipfs_api_backend_hyper::{ApiError, Error};
+use ipfs_webdav::api::{PeerApi, PeerEntry, PeerError};
+
+/// `PeerApi` whose lookups succeed for `/dir` and `/file` while every other
+/// call fails with the given error.
+#[derive(Debug)]
+struct FailingApi {
+    error: fn() -> PeerError,
+}
+
+impl FailingApi {
+    fn error(&self) -> PeerError {
+        (self.error)()
+    }
+}
+
+#[async_trait]
+impl PeerApi for FailingApi {
+    async fn cp(&self, _path: &str, _dest: &str) -> Result<(), PeerError> {
+        Err(self.error())
+    }
+
+    async fn flush(&self, _path: &str) -> Result<(), PeerError> {
+        Err(self.error())
+    }
+
+    async fn ls(&self, _path: &str) -> Result<Vec<PeerEntry>, PeerError> {
+        Ok(Vec::new())
+    }
+
+    async fn mkdir(&self, _path: &str) -> Result<PeerEntry, PeerError> {
+        Err(self.error())
+    }
+
+    async fn mv(&self, _path: &str, _dest: &str) -> Result<(), PeerError> {
+        Err(self.error())
+    }
+
+    async fn read(&self, _path: &str, _offset: usize, _count: usize) -> Result<Bytes, PeerError> {
+        Err(self.error())
+    }
+
+    async fn rm(&self, _path: &str) -> Result<(), PeerError> {
+        Err(self.error())
+    }
+
+    async fn stat(&self, path: &str) -> Result<PeerEntry, PeerError> {
+        let is_dir = match path {
+            "/" | "/dir" => true,
+            "/file" => false,
+            _ => return Err(PeerError::NotFound),
+        };
+        Ok(PeerEntry {
+            path: path.to_string(),
+            crtime: SystemTime::UNIX_EPOCH,
+            mtime: SystemTime::UNIX_EPOCH,
+            is_dir,
+            size: if is_dir { 0 } else { 3 },
+            cid: None,
+        })
+    }
+
+    async fn write(
+        &self,
+        _path: &str,
+        _offset: usize,
+        _truncate: bool,
+        _data: Bytes,
+    ) -> Result<(), PeerError> {
+        Err(self.error())
+    }
+}
+
+async fn status(error: fn() -> PeerError, method: &str, path: &str) -> StatusCode {
+    let server = ipfs_webdav::make_server(Box::new(FailingApi { error }));
+    let mut req = Request::builder().method(method).uri(path);
+    if method == "MOVE" || method == "COPY" {
+        req = req.header("Destination", "http://localhost/other");
+    }
+    let body = if method == "PUT" { "abc" } else { "" };
+    let req = req.body(hyper::Body::from(body)).unwrap();
+    server.handle(req).await.status()
+}
+
+fn kubo_error(message: &str) -> PeerError {
+    PeerError::from(Error::Api(ApiError {
+        message: message.to_string(),
+        code: 0,
+    }))
+}
+
+#[test]
+fn kubo_messages_are_classified() {
+    let cases = [
+        ("file does not exist", "NotFound"),
+        (
+            "cp: cannot get node from path /x: no link named \"x\"",
+            "NotFound",
+        ),
+        ("file already exists", "AlreadyExists"),
+        ("directory already has entry by that name", "AlreadyExists"),
+        (
+            "/dir is a directory, use -r to remove directories",
+            "NotEmpty",
+        ),
+        ("write /data/blocks: no space left on device", "NoSpace"),
+        (
+            "dial tcp 127.0.0.1:5001: connect: connection refused",
+            "Unavailable",
+        ),
+        ("context deadline exceeded", "Timeout"),
+    ];
+    for (message, kind) in cases {
+        let err = kubo_error(message);
+        assert!(
+            format!("{:?}", err).starts_with(kind),
+            "{}: {:?}",
+            message,
+            err
+        );
+    }
+    let err = kubo_error("unexpected failure");
+    assert!(matches!(err, PeerError::Other(_)));
+    assert!(std::error::Error::source(&err).is_some());
+}
+
+#[tokio::test]
+async fn delete_missing_file_is_not_found() {
+    let status = status(|| PeerError::NotFound, "DELETE", "/file").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn delete_with_unreachable_node_is_bad_gateway() {
+    let status = status(|| PeerError::Unavailable, "DELETE", "/file").await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn delete_non_empty_dir_is_forbidden() {
+    let status = status(|| PeerError::NotEmpty, "DELETE", "/dir").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn mkcol_existing_is_not_allowed() {
+    let status = status(|| PeerError::AlreadyExists, "MKCOL", "/new").await;
+    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[tokio::test]
+async fn put_without_space_is_insufficient_storage() {
+    let status = status(|| PeerError::NoSpace, "PUT", "/new").await;
+    assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+}
+
+#[tokio::test]
+async fn move_onto_existing_is_not_allowed() {
+    let status = status(|| PeerError::AlreadyExists, "MOVE", "/file").await;
+    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[tokio::test]
+async fn copy_with_timeout_is_bad_gateway() {
+    let status = status(|| PeerError::Timeout, "COPY", "/file").await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn copy_with_unknown_failure_is_internal_error() {
+    let status = status(|| PeerError::other("boom"), "COPY", "/file").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+}
@@ -0,0 +1,236 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+mod common;
+
+use std::sync::Arc;
+
+use common::{get, mkcol, propfind, put, request, RecordingApi};
+use http::StatusCode;
+use ipfs_webdav::api::{InMemoryApi, PeerApi, PeerError};
+use ipfs_webdav::{BuildError, IpfsWebDav, IpfsWebDavBuilder};
+
+// Serves a share with `/docs` holding two files, returning the CID of `/docs`.
+async fn immutable_server(enabled: bool) -> (Arc<InMemoryApi>, IpfsWebDav, String) {
+    let api: Arc<InMemoryApi> = InMemoryApi::new().into();
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .immutable_paths(enabled)
+        .build()
+        .unwrap();
+    mkcol(&server, "/docs/").await;
+    put(&server, "/docs/a.txt", "0123456789").await;
+    put(&server, "/docs/b.txt", "b").await;
+    let cid = api.stat("/docs").await.unwrap().cid.unwrap();
+    (api, server, cid)
+}
+
+#[tokio::test]
+async fn content_is_browsed_by_cid() {
+    let (_, server, cid) = immutable_server(true).await;
+    let dir = format!("/ipfs/{}/", cid);
+
+    let res = propfind(&server, &dir, "1").await;
+    assert_eq!(res.status, StatusCode::MULTI_STATUS);
+    assert!(res.body.contains(&format!("{}a.txt", dir)), "{}", res.body);
+    assert!(res.body.contains(&format!("{}b.txt", dir)), "{}", res.body);
+    assert!(res.body.contains(&cid), "{}", res.body);
+
+    let file = format!("{}a.txt", dir);
+    assert_eq!(get(&server, &file).await.body, "0123456789");
+    let headers = [("Range", "bytes=4-6")];
+    let res = request(&server, "GET", &file, &headers, "").await;
+    assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.body, "456");
+
+    let res = get(&server, "/ipfs/unknown/a.txt").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+    let res = propfind(&server, "/", "1").await;
+    assert!(res.body.contains("<D:href>/ipfs/</D:href>"), "{}", res.body);
+    assert!(res.body.contains("<D:href>/ipns/</D:href>"), "{}", res.body);
+}
+
+#[tokio::test]
+async fn content_is_browsed_by_name() {
+    let (api, server, cid) = immutable_server(true).await;
+    api.set_name("k51docs", &format!("/ipfs/{}", cid));
+    assert_eq!(get(&server, "/ipns/k51docs/b.txt").await.body, "b");
+    let res = propfind(&server, "/ipns/k51docs/", "1").await;
+    assert!(
+        res.body.contains("<D:href>/ipns/k51docs/a.txt</D:href>"),
+        "{}",
+        res.body
+    );
+    assert_eq!(
+        get(&server, "/ipns/unknown/b.txt").await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn content_is_read_only_but_can_be_copied() {
+    let (api, server, cid) = immutable_server(true).await;
+    let file = format!("/ipfs/{}/a.txt", cid);
+    for method in ["PUT", "DELETE", "MKCOL"] {
+        let res = request(&server, method, &file, &[], "").await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN, "{}", method);
+    }
+    let headers = [("Destination", "/moved.txt")];
+    let res = request(&server, "MOVE", &file, &headers, "").await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+
+    let headers = [("Destination", "/copy.txt")];
+    let res = request(&server, "COPY", &file, &headers, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(get(&server, "/copy.txt").await.body, "0123456789");
+
+    let headers = [("Destination", "/copy/")];
+    let res = request(&server, "COPY", &format!("/ipfs/{}/", cid), &headers, "").await;
+    assert_eq!(res.status, StatusCode::CREATED);
+    assert_eq!(get(&server, "/copy/b.txt").await.body, "b");
+    assert_eq!(api.ls("/copy").await.unwrap().len(), 2);
+
+    let dest = format!("/ipfs/{}/c.txt", cid);
+    let headers = [("Destination", dest.as_str())];
+    let res = request(&server, "COPY", "/copy.txt", &headers, "").await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn namespaces_are_off_by_default() {
+    let (_, server, cid) = immutable_server(false).await;
+    let res = get(&server, &format!("/ipfs/{}/a.txt", cid)).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+    let res = propfind(&server, "/", "1").await;
+    assert!(!res.body.contains("/ipfs/"), "{}", res.body);
+}
+
+#[tokio::test]
+async fn namespaces_can_be_mounted_elsewhere() {
+    let api: Arc<InMemoryApi> = InMemoryApi::new().into();
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .immutable_paths(true)
+        .immutable_mounts(".ipfs", ".ipns")
+        .build()
+        .unwrap();
+    mkcol(&server, "/ipfs/").await;
+    put(&server, "/ipfs/a.txt", "a").await;
+    let cid = api.stat("/ipfs").await.unwrap().cid.unwrap();
+
+    assert_eq!(get(&server, "/ipfs/a.txt").await.body, "a");
+    let res = get(&server, &format!("/.ipfs/{}/a.txt", cid)).await;
+    assert_eq!(res.body, "a");
+    let res = propfind(&server, "/", "1").await;
+    assert!(res.body.contains("<D:href>/ipfs/</D:href>"), "{}", res.body);
+    assert!(
+        res.body.contains("<D:href>/.ipns/</D:href>"),
+        "{}",
+        res.body
+    );
+
+    let res = IpfsWebDavBuilder::new(InMemoryApi::new())
+        .immutable_mounts("a/b", "ipns")
+        .build();
+    assert!(matches!(res, Err(BuildError::InvalidMount(_))));
+}
+
+fn ipns_key_update(key: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:I="https://ipfs.tech/ns"><D:set><D:prop><I:ipns-key>{}</I:ipns-key></D:prop></D:set></D:propertyupdate>"#,
+        key
+    )
+}
+
+#[tokio::test]
+async fn directories_are_published_by_key() {
+    let (api, server, cid) = immutable_server(true).await;
+    api.add_key("docs");
+
+    let res = request(
+        &server,
+        "PROPPATCH",
+        "/docs/",
+        &[],
+        &ipns_key_update("docs"),
+    )
+    .await;
+    assert_eq!(res.status, StatusCode::MULTI_STATUS);
+    assert!(res.body.contains("200 OK"), "{}", res.body);
+    let target = api.resolve_name("k51docs").await.unwrap();
+    assert_eq!(target, format!("/ipfs/{}", cid));
+    assert_eq!(get(&server, "/ipns/k51docs/b.txt").await.body, "b");
+
+    let res = propfind(&server, "/docs/", "0").await;
+    assert!(res.body.contains(">docs</"), "{}", res.body);
+
+    let res = request(
+        &server,
+        "PROPPATCH",
+        "/docs/",
+        &[],
+        &ipns_key_update("nope"),
+    )
+    .await;
+    assert_eq!(res.status, StatusCode::MULTI_STATUS);
+    assert!(res.body.contains("409"), "{}", res.body);
+    assert!(matches!(
+        api.resolve_name("k51nope").await,
+        Err(PeerError::NotFound)
+    ));
+}
+
+#[tokio::test]
+async fn key_is_stored_before_it_is_published() {
+    let api = RecordingApi::new();
+    api.inner().add_key("docs");
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .build()
+        .unwrap();
+    mkcol(&server, "/docs/").await;
+
+    api.fail("name_publish");
+    let update = ipns_key_update("docs");
+    let res = request(&server, "PROPPATCH", "/docs/", &[], &update).await;
+    assert!(!res.status.is_success(), "{}", res.status);
+    let res = propfind(&server, "/docs/", "0").await;
+    assert!(!res.body.contains("ipns-key"), "{}", res.body);
+    let writes = api.paths("write");
+    assert!(!writes.is_empty());
+    assert!(
+        writes.iter().all(|p| p.ends_with("props.json")),
+        "{:?}",
+        writes
+    );
+    assert!(api.paths("rm").iter().any(|p| p.ends_with("props.json")));
+}
+
+#[tokio::test]
+async fn content_is_looked_up_once() {
+    let api = RecordingApi::new();
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .immutable_paths(true)
+        .build()
+        .unwrap();
+    mkcol(&server, "/docs/").await;
+    put(&server, "/docs/a.txt", "0123456789").await;
+    put(&server, "/docs/b.txt", "b").await;
+    let cid = api.stat("/docs").await.unwrap().cid.unwrap();
+    let dir = format!("/ipfs/{}/", cid);
+
+    api.clear();
+    for _ in 0..2 {
+        let res = propfind(&server, &dir, "1").await;
+        assert_eq!(res.body.matches("<D:href>").count(), 3, "{}", res.body);
+        let res = propfind(&server, &format!("{}a.txt", dir), "0").await;
+        assert!(res.body.contains("cid"), "{}", res.body);
+    }
+    // the listing gave the entries of the files
+    assert_eq!(api.count(&["ipfs_stat"]), 1);
+    assert_eq!(api.count(&["ipfs_ls"]), 2);
+}
@@ -0,0 +1,181 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use std::convert::Infallible;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use ipfs_webdav::api::{BaseApi, DagOptions, PeerApi};
+use ipfs_webdav::IpfsWebDavBuilder;
+
+/// Kubo RPC requests received by a `fake_node`, as path and query.
+type Calls = Arc<Mutex<Vec<String>>>;
+
+/// Starts a node that accepts every RPC call and records it.
+///
+/// It knows the root, the directories made and the files written, whose stat
+/// tells their type; every other path does not exist.
+fn fake_node() -> (String, Calls) {
+    let calls = Calls::default();
+    let recorded = calls.clone();
+    let make_svc = make_service_fn(move |_| {
+        let calls = recorded.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let calls = calls.clone();
+                async move {
+                    let uri = req.uri().to_string();
+                    hyper::body::to_bytes(req.into_body()).await.unwrap();
+                    let res = answer(&calls.lock().unwrap(), &uri);
+                    calls.lock().unwrap().push(uri);
+                    Ok::<_, Infallible>(res)
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+    let uri = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    (uri, calls)
+}
+
+fn answer(calls: &[String], uri: &str) -> Response<Body> {
+    let (method, query) = uri.split_once('?').unwrap_or((uri, ""));
+    let body = match method {
+        "/api/v0/add" => r#"{"Name":"a.txt","Hash":"QmAdded","Size":"4"}"#,
+        "/api/v0/files/stat" => {
+            let made = |m: &str| {
+                calls
+                    .iter()
+                    .filter_map(|c| c.split_once('?'))
+                    .any(|(method, q)| method == m && arg(q) == arg(query))
+            };
+            if arg(query) == "%2F" || made("/api/v0/files/mkdir") {
+                r#"{"Hash":"QmDir","Size":0,"Type":"directory"}"#
+            } else if made("/api/v0/files/write") {
+                r#"{"Hash":"QmFile","Size":0,"Type":"file"}"#
+            } else {
+                let mut res = Response::new(Body::from(
+                    r#"{"Message":"file does not exist","Code":0,"Type":"error"}"#,
+                ));
+                *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                return res;
+            }
+        }
+        _ => "",
+    };
+    Response::new(Body::from(body))
+}
+
+fn arg(query: &str) -> &str {
+    let mut params = query.split('&');
+    params.find_map(|p| p.strip_prefix("arg=")).unwrap_or("")
+}
+
+#[tokio::test]
+async fn mfs_changes_leave_flushing_to_the_flusher() {
+    let (uri, calls) = fake_node();
+    let api = BaseApi::from_uri(&uri);
+    let options = DagOptions {
+        cid_version: Some(1),
+        ..Default::default()
+    };
+    api.mkdir_with("/d", &options).await.unwrap();
+    api.cp("/d", "/e").await.unwrap();
+    api.mv("/e", "/f").await.unwrap();
+    api.rm("/f").await.unwrap();
+    api.chcid("/d", &options).await.unwrap();
+    let data = Box::new(Cursor::new(b"data".to_vec()));
+    api.add("/d/a.txt", data, &options).await.unwrap();
+    api.flush("/").await.unwrap();
+
+    let calls = calls.lock().unwrap();
+    let changes: Vec<&String> = calls
+        .iter()
+        .filter(|c| c.starts_with("/api/v0/files/"))
+        .filter(|c| !c.starts_with("/api/v0/files/stat") && !c.starts_with("/api/v0/files/flush"))
+        .collect();
+    assert_eq!(changes.len(), 7, "{:?}", changes);
+    for call in &changes {
+        assert!(call.contains("flush=false"), "{}", call);
+    }
+    // added unpinned and linked from the node's copy
+    assert!(calls
+        .iter()
+        .any(|c| c.starts_with("/api/v0/add") && c.contains("pin=false")));
+    let cp = "arg=%2Fipfs%2FQmAdded";
+    assert!(calls
+        .iter()
+        .any(|c| c.starts_with("/api/v0/files/cp") && c.contains(cp)));
+    let flushes = calls
+        .iter()
+        .filter(|c| c.starts_with("/api/v0/files/flush"));
+    assert_eq!(flushes.count(), 1);
+}
+
+// Measures how fast a 64 MiB upload reaches the node over HTTP, once written
+// chunk by chunk as webdav-handler hands it over and once through the buffered
+// server. The node only receives the data, so this is the cost of the round
+// trips alone, without the DAG rewrite Kubo does for every files/write. Run with
+// `cargo test --release --test kubo -- --ignored --nocapture`.
+#[tokio::test]
+#[ignore]
+async fn upload_throughput() {
+    const SIZE: usize = 64 * 1024 * 1024;
+    const CHUNK: usize = 64 * 1024;
+    let data = Bytes::from(vec![7u8; SIZE]);
+    let chunks: Vec<Bytes> = (0..SIZE)
+        .step_by(CHUNK)
+        .map(|at| data.slice(at..at + CHUNK))
+        .collect();
+
+    let (uri, calls) = fake_node();
+    let api = BaseApi::from_uri(&uri);
+    let started = Instant::now();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let offset = i * CHUNK;
+        api.write("/chunked.bin", offset, offset == 0, chunk.clone())
+            .await
+            .unwrap();
+    }
+    report("per chunk", started.elapsed(), &calls);
+
+    let (uri, calls) = fake_node();
+    let server = IpfsWebDavBuilder::new(BaseApi::from_uri(&uri))
+        .build()
+        .unwrap();
+    let body = futures::stream::iter(chunks.into_iter().map(Ok::<_, Infallible>));
+    let req = Request::builder()
+        .method("PUT")
+        .uri("/buffered.bin")
+        .body(Body::wrap_stream(body))
+        .unwrap();
+    let started = Instant::now();
+    let res = server.handle(req).await;
+    assert!(res.status().is_success(), "{}", res.status());
+    report("buffered", started.elapsed(), &calls);
+}
+
+fn report(name: &str, took: Duration, calls: &Calls) {
+    let calls = calls.lock().unwrap();
+    let writes = calls
+        .iter()
+        .filter(|c| c.starts_with("/api/v0/files/write"));
+    println!(
+        "{}: {} files/write calls, {} RPCs in total, {:.0} MiB/s",
+        name,
+        writes.count(),
+        calls.len(),
+        64.0 / took.as_secs_f64()
+    );
+}
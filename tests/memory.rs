@@ -0,0 +1,145 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+use bytes::Bytes;
+use ipfs_webdav::api::{InMemoryApi, PeerApi, PeerEntry, PeerError};
+
+async fn read_all(api: &InMemoryApi, path: &str) -> Bytes {
+    api.read(path, 0, usize::MAX).await.unwrap()
+}
+
+async fn names(api: &InMemoryApi, path: &str) -> Vec<String> {
+    let mut names: Vec<String> = api
+        .ls(path)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|e| e.path)
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn write_at_offset_and_truncate() {
+    let api = InMemoryApi::new();
+    api.write("/f", 0, false, Bytes::from("hello world"))
+        .await
+        .unwrap();
+    api.write("/f", 6, false, Bytes::from("there"))
+        .await
+        .unwrap();
+    assert_eq!(read_all(&api, "/f").await, "hello there");
+    assert_eq!(api.read("/f", 2, 3).await.unwrap(), "llo");
+
+    api.write("/f", 0, true, Bytes::from("bye")).await.unwrap();
+    assert_eq!(read_all(&api, "/f").await, "bye");
+    assert_eq!(api.stat("/f").await.unwrap().size, 3);
+
+    // the gap is filled with zeros, like MFS does
+    api.write("/f", 5, false, Bytes::from("x")).await.unwrap();
+    assert_eq!(read_all(&api, "/f").await, "bye\0\0x");
+    let err = api.write("/missing/f", 0, false, Bytes::new()).await;
+    assert!(matches!(err, Err(PeerError::NotFound)));
+}
+
+#[tokio::test]
+async fn cids_follow_content() {
+    let api = InMemoryApi::new();
+    for dir in ["/a", "/b"] {
+        api.mkdir(dir).await.unwrap();
+        api.write(&format!("{}/f", dir), 0, false, Bytes::from("x"))
+            .await
+            .unwrap();
+    }
+    let cid = |entry: PeerEntry| entry.cid.unwrap();
+    let a = cid(api.stat("/a").await.unwrap());
+    assert_eq!(a, cid(api.stat("/b").await.unwrap()));
+    let root = cid(api.stat("/").await.unwrap());
+
+    api.write("/b/f", 0, true, Bytes::from("y")).await.unwrap();
+    assert_ne!(a, cid(api.stat("/b").await.unwrap()));
+    assert_ne!(root, cid(api.stat("/").await.unwrap()));
+    let entry = api.ipfs_stat(&format!("/ipfs/{}/f", a)).await.unwrap();
+    assert_eq!(entry.cid, api.stat("/a/f").await.unwrap().cid);
+}
+
+#[tokio::test]
+async fn mkdir_and_ls() {
+    let api = InMemoryApi::new();
+    let entry = api.mkdir("/a").await.unwrap();
+    assert!(entry.is_dir);
+    api.mkdir("/a/b").await.unwrap();
+    api.write("/a/f", 0, false, Bytes::from("x")).await.unwrap();
+    api.write("/a-b", 0, false, Bytes::from("y")).await.unwrap();
+
+    assert!(matches!(
+        api.mkdir("/a").await,
+        Err(PeerError::AlreadyExists)
+    ));
+    assert!(matches!(api.mkdir("/x/y").await, Err(PeerError::NotFound)));
+    assert_eq!(names(&api, "/").await, ["/a", "/a-b"]);
+    assert_eq!(names(&api, "/a/").await, ["/a/b", "/a/f"]);
+    assert_eq!(names(&api, "/a/f").await, ["/a/f"]);
+}
+
+#[tokio::test]
+async fn cp_copies_subtree() {
+    let api = InMemoryApi::new();
+    api.mkdir("/a").await.unwrap();
+    api.write("/a/f", 0, false, Bytes::from("x")).await.unwrap();
+    api.cp("/a", "/b").await.unwrap();
+    assert_eq!(read_all(&api, "/a/f").await, "x");
+    assert_eq!(read_all(&api, "/b/f").await, "x");
+
+    assert!(matches!(
+        api.cp("/a", "/b").await,
+        Err(PeerError::AlreadyExists)
+    ));
+    assert!(matches!(api.cp("/c", "/d").await, Err(PeerError::NotFound)));
+    api.cp("/a/f", "/b/").await.unwrap_err();
+    api.cp("/a/f", "/").await.unwrap();
+    assert_eq!(read_all(&api, "/f").await, "x");
+}
+
+#[tokio::test]
+async fn mv_renames_and_moves_into_dirs() {
+    let api = InMemoryApi::new();
+    api.mkdir("/a").await.unwrap();
+    api.mkdir("/d").await.unwrap();
+    api.write("/a/f", 0, false, Bytes::from("x")).await.unwrap();
+    api.write("/g", 0, false, Bytes::from("y")).await.unwrap();
+
+    api.mv("/a", "/b").await.unwrap();
+    assert!(matches!(api.stat("/a/f").await, Err(PeerError::NotFound)));
+    assert_eq!(read_all(&api, "/b/f").await, "x");
+
+    api.mv("/b", "/d").await.unwrap();
+    assert_eq!(read_all(&api, "/d/b/f").await, "x");
+
+    api.mv("/g", "/d/b/f").await.unwrap();
+    assert_eq!(read_all(&api, "/d/b/f").await, "y");
+    assert!(matches!(
+        api.mv("/d", "/d/b/e").await,
+        Err(PeerError::PermissionDenied)
+    ));
+}
+
+#[tokio::test]
+async fn rm_removes_subtree() {
+    let api = InMemoryApi::new();
+    api.mkdir("/a").await.unwrap();
+    api.write("/a/f", 0, false, Bytes::from("x")).await.unwrap();
+    api.rm("/a").await.unwrap();
+    assert!(matches!(api.stat("/a/f").await, Err(PeerError::NotFound)));
+    assert!(matches!(api.rm("/a").await, Err(PeerError::NotFound)));
+    assert!(matches!(
+        api.rm("/").await,
+        Err(PeerError::PermissionDenied)
+    ));
+}
@@ -0,0 +1,51 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+mod common;
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use common::{request, RecordingApi};
+use http::StatusCode;
+use ipfs_webdav::api::PeerApi;
+use ipfs_webdav::{IpfsWebDav, IpfsWebDavBuilder};
+
+async fn streaming_server(len: usize) -> (Arc<RecordingApi>, IpfsWebDav, Vec<u8>) {
+    let api = RecordingApi::streaming(1000);
+    let content: Vec<u8> = (0..len).map(|i| b'a' + (i % 26) as u8).collect();
+    api.write("/f.bin", 0, false, Bytes::from(content.clone()))
+        .await
+        .unwrap();
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .read_ahead(4)
+        .build()
+        .unwrap();
+    (api, server, content)
+}
+
+#[tokio::test]
+async fn get_opens_one_stream() {
+    let (api, server, content) = streaming_server(300 * 1024).await;
+    let res = request(&server, "GET", "/f.bin", &[], "").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.body.as_bytes() == content);
+    assert_eq!(api.count(&["read_stream"]), 1);
+    assert_eq!(api.count(&["read"]), 0);
+}
+
+#[tokio::test]
+async fn range_opens_one_stream_at_its_start() {
+    let (api, server, content) = streaming_server(100 * 1024).await;
+    let headers = [("Range", "bytes=50000-70000")];
+    let res = request(&server, "GET", "/f.bin", &headers, "").await;
+    assert_eq!(res.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.header("content-length"), "20001");
+    assert!(res.body.as_bytes() == &content[50000..=70000]);
+    assert_eq!(api.count(&["read_stream"]), 1);
+}
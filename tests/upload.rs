@@ -0,0 +1,330 @@
+// Copyright 2022-2023 Debox Network
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+mod common;
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use common::{get, mkcol, put, request, RecordingApi};
+use http::{Request, StatusCode};
+use ipfs_webdav::api::{DagOptions, InMemoryApi, PeerApi};
+use ipfs_webdav::{FlushPolicy, IpfsWebDav, IpfsWebDavBuilder, WritePolicy};
+
+fn counting_server(policy: WritePolicy) -> (Arc<RecordingApi>, IpfsWebDav) {
+    let api = RecordingApi::new();
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .write_policy(policy)
+        .build()
+        .unwrap();
+    (api, server)
+}
+
+// Sends the body in chunks of 64 KiB, like a client streaming a large file.
+async fn put_chunked(server: &IpfsWebDav, path: &str, body: &[u8]) {
+    let chunks: Vec<Result<Bytes, std::io::Error>> = body
+        .chunks(64 * 1024)
+        .map(|c| Ok(Bytes::copy_from_slice(c)))
+        .collect();
+    let req = Request::builder()
+        .method("PUT")
+        .uri(path)
+        .body(hyper::Body::wrap_stream(futures::stream::iter(chunks)))
+        .unwrap();
+    let res = server.handle(req).await;
+    assert!(res.status().is_success(), "PUT {}: {}", path, res.status());
+}
+
+fn content(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn upload_is_written_in_few_calls() {
+    let (api, server) = counting_server(WritePolicy::default());
+    let body = content(3 * 1024 * 1024);
+    put_chunked(&server, "/big.bin", &body).await;
+    assert_eq!(api.count(&["write", "write_from"]), 1);
+
+    let stored = api.read("/big.bin", 0, usize::MAX).await.unwrap();
+    assert_eq!(stored.len(), body.len());
+    assert!(stored == body);
+}
+
+#[tokio::test]
+async fn large_upload_is_spooled() {
+    let spool = std::env::temp_dir();
+    let (api, server) = counting_server(WritePolicy {
+        buffer_size: 1000,
+        spool_dir: Some(spool),
+        ..Default::default()
+    });
+    let body = content(300 * 1024);
+    put_chunked(&server, "/big.bin", &body).await;
+    assert_eq!(api.count(&["write", "write_from"]), 1);
+
+    let res = server
+        .handle(Request::get("/big.bin").body(hyper::Body::empty()).unwrap())
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let stored = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert!(stored == body);
+}
+
+#[tokio::test]
+async fn overwrite_and_empty_upload() {
+    let (api, server) = counting_server(WritePolicy::default());
+    put(&server, "/f.txt", "a longer first version").await;
+    put(&server, "/f.txt", "short").await;
+    assert_eq!(get(&server, "/f.txt").await.body, "short");
+
+    put(&server, "/f.txt", "").await;
+    let res = get(&server, "/f.txt").await;
+    assert_eq!(res.header("content-length"), "0");
+    assert_eq!(api.count(&["write", "write_from"]), 3);
+}
+
+#[tokio::test]
+async fn atomic_upload_replaces_destination() {
+    let api: Arc<InMemoryApi> = InMemoryApi::new().into();
+    api.mkdir("/.ipfs-webdav").await.unwrap();
+    api.mkdir("/.ipfs-webdav/uploads").await.unwrap();
+    api.write("/.ipfs-webdav/uploads/orphan", 0, false, Bytes::from("x"))
+        .await
+        .unwrap();
+    // files of another instance, one from long ago and one still being uploaded,
+    // and the directory of an instance of an earlier version
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let fresh = format!("/.ipfs-webdav/uploads/{:x}-2-0-1", now.as_nanos());
+    for file in ["/.ipfs-webdav/uploads/1-2-0-0", &fresh] {
+        api.write(file, 0, false, Bytes::from("x")).await.unwrap();
+    }
+    api.mkdir("/.ipfs-webdav/uploads/1-3-0").await.unwrap();
+    for _ in 0..2 {
+        let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+            .write_policy(WritePolicy {
+                atomic: true,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+
+        put(&server, "/f.txt", "first").await;
+        put(&server, "/f.txt", "second").await;
+        put(&server, "/empty.txt", "").await;
+        assert_eq!(get(&server, "/f.txt").await.body, "second");
+        assert_eq!(get(&server, "/empty.txt").await.status, StatusCode::OK);
+    }
+    // neither instance left anything behind
+    let left: Vec<String> = api
+        .ls("/.ipfs-webdav/uploads")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|e| e.path)
+        .collect();
+    assert_eq!(left, vec![fresh]);
+}
+
+#[tokio::test]
+async fn whole_files_are_added() {
+    let (api, server) = counting_server(WritePolicy {
+        add: true,
+        ..Default::default()
+    });
+    let body = content(200 * 1024);
+    put_chunked(&server, "/big.bin", &body).await;
+    put(&server, "/big.bin", "replaced").await;
+    put(&server, "/empty.txt", "").await;
+    assert_eq!(api.count(&["add"]), 2);
+    // a time on the node would change the CID `ipfs add` gave the file
+    assert_eq!(api.count(&["touch"]), 0);
+    assert_eq!(get(&server, "/big.bin").await.body, "replaced");
+    assert_eq!(get(&server, "/empty.txt").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn dag_options_follow_collections() {
+    let api = RecordingApi::new();
+    let v1 = DagOptions {
+        cid_version: Some(1),
+        ..Default::default()
+    };
+    let blake3 = DagOptions {
+        hash: Some("blake3".to_string()),
+        ..Default::default()
+    };
+    let raw = DagOptions {
+        raw_leaves: Some(true),
+        cid_version: Some(0),
+        ..Default::default()
+    };
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .dag_options(v1.clone())
+        .collection_dag_options("/data/", blake3.clone())
+        .collection_dag_options("/data/raw", raw.clone())
+        .build()
+        .unwrap();
+
+    put(&server, "/f.txt", "f").await;
+    mkcol(&server, "/data/").await;
+    mkcol(&server, "/data/raw/").await;
+    put(&server, "/data/raw/g.txt", "g").await;
+    put(&server, "/database.txt", "d").await;
+
+    let dags = api.dags();
+    let v1_blake3 = blake3.clone().or(&v1);
+    let expected = vec![
+        ("chcid", "/".to_string(), v1.clone()),
+        ("chcid", "/data".to_string(), v1_blake3.clone()),
+        ("chcid", "/data/raw".to_string(), raw.clone().or(&v1_blake3)),
+        ("write_from", "/f.txt".to_string(), v1.clone()),
+        ("mkdir_with", "/data".to_string(), v1_blake3.clone()),
+        (
+            "mkdir_with",
+            "/data/raw".to_string(),
+            raw.clone().or(&v1_blake3),
+        ),
+        (
+            "write_from",
+            "/data/raw/g.txt".to_string(),
+            raw.or(&v1_blake3),
+        ),
+        ("write_from", "/database.txt".to_string(), v1),
+    ];
+    assert_eq!(dags, expected);
+}
+
+#[tokio::test]
+async fn interrupted_upload_can_be_resumed() {
+    let (api, server) = counting_server(WritePolicy::default());
+    let chunks: Vec<Result<Bytes, std::io::Error>> = vec![
+        Ok(Bytes::from("0123")),
+        Ok(Bytes::from("4567")),
+        Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "gone")),
+    ];
+    let req = Request::builder()
+        .method("PUT")
+        .uri("/resume.txt")
+        .body(hyper::Body::wrap_stream(futures::stream::iter(chunks)))
+        .unwrap();
+    let res = server.handle(req).await;
+    assert!(!res.status().is_success());
+
+    // what arrived is written before the next request looks at the file
+    let res = request(&server, "HEAD", "/resume.txt", &[], "").await;
+    assert_eq!(res.header("content-length"), "8");
+    assert_eq!(
+        api.read("/resume.txt", 0, usize::MAX).await.unwrap(),
+        "01234567"
+    );
+
+    let headers = [("Content-Range", "bytes 10-11/12")];
+    let res = request(&server, "PUT", "/resume.txt", &headers, "ab").await;
+    assert_eq!(res.status, StatusCode::RANGE_NOT_SATISFIABLE);
+
+    let headers = [("Content-Range", "bytes 8-11/12")];
+    let res = request(&server, "PUT", "/resume.txt", &headers, "89ab").await;
+    assert!(res.status.is_success(), "{}", res.status);
+    assert_eq!(get(&server, "/resume.txt").await.body, "0123456789ab");
+}
+
+#[tokio::test]
+async fn dag_options_apply_below_the_share_root() {
+    let api = RecordingApi::new();
+    let v1 = DagOptions {
+        cid_version: Some(1),
+        ..Default::default()
+    };
+    let blake3 = DagOptions {
+        hash: Some("blake3".to_string()),
+        ..Default::default()
+    };
+    let server = IpfsWebDavBuilder::new(Box::new(api.clone()))
+        .root("/shares/a")
+        .dag_options(v1.clone())
+        .collection_dag_options("/data/", blake3.clone())
+        .build()
+        .unwrap();
+
+    mkcol(&server, "/data/").await;
+    put(&server, "/data/f.txt", "f").await;
+
+    let v1_blake3 = blake3.or(&v1);
+    let expected = vec![
+        ("chcid", "/shares/a".to_string(), v1),
+        ("chcid", "/shares/a/data".to_string(), v1_blake3.clone()),
+        (
+            "mkdir_with",
+            "/shares/a/data".to_string(),
+            v1_blake3.clone(),
+        ),
+        ("write_from", "/shares/a/data/f.txt".to_string(), v1_blake3),
+    ];
+    assert_eq!(api.dags(), expected);
+}
+
+fn flushing_server(policy: FlushPolicy) -> (Arc<RecordingApi>, IpfsWebDav, ipfs_webdav::Flusher) {
+    let api = RecordingApi::new();
+    let builder = IpfsWebDavBuilder::new(Box::new(api.clone())).flush_policy(policy);
+    let flusher = builder.flusher();
+    (api, builder.build().unwrap(), flusher)
+}
+
+fn flushes(api: &RecordingApi) -> Vec<String> {
+    api.paths("flush")
+}
+
+#[tokio::test]
+async fn uploads_are_flushed_when_closed() {
+    let (api, server, flusher) = flushing_server(FlushPolicy::Close);
+    put_chunked(&server, "/big.bin", &content(300 * 1024)).await;
+    put(&server, "/f.txt", "f").await;
+    assert_eq!(flushes(&api), vec!["/big.bin", "/f.txt"]);
+    assert!(!flusher.is_pending());
+}
+
+#[tokio::test]
+async fn uploads_are_flushed_per_write() {
+    let (api, server, _) = flushing_server(FlushPolicy::Write);
+    put_chunked(&server, "/big.bin", &content(300 * 1024)).await;
+    // once after the buffered upload is written, once more for its mtime
+    assert_eq!(flushes(&api), vec!["/big.bin", "/big.bin"]);
+}
+
+#[tokio::test]
+async fn uploads_are_flushed_on_demand() {
+    let (api, server, flusher) = flushing_server(FlushPolicy::Manual);
+    put_chunked(&server, "/big.bin", &content(300 * 1024)).await;
+    put(&server, "/f.txt", "f").await;
+    assert!(flushes(&api).is_empty());
+    assert!(flusher.is_pending());
+
+    flusher.flush().await.unwrap();
+    flusher.flush().await.unwrap();
+    assert_eq!(flushes(&api), vec!["/"]);
+    assert!(!flusher.is_pending());
+}
+
+#[tokio::test]
+async fn uploads_are_flushed_periodically() {
+    let interval = Duration::from_millis(50);
+    let (api, server, flusher) = flushing_server(FlushPolicy::Periodic(interval));
+    put(&server, "/f.txt", "f").await;
+    put(&server, "/g.txt", "g").await;
+    assert!(flushes(&api).is_empty());
+
+    for _ in 0..100 {
+        if !flusher.is_pending() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(flushes(&api), vec!["/"]);
+}